@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::Listener;
+
+const CHALLENGE_PATH: &str = "/.well-known/acme-challenge/";
+
+/// A tiny HTTP server answering 'http-01' challenges for as long as it's alive.
+/// ([RFC 8555§8.3](https://www.rfc-editor.org/rfc/rfc8555.html#section-8.3))
+pub struct StandaloneServer {
+    responses: Arc<Mutex<HashMap<String, String>>>,
+    _listener: Listener,
+}
+
+impl StandaloneServer {
+    /// Start listening on the given port, on all addresses.
+    pub fn start(port: u16) -> std::io::Result<Self> {
+        let responses = Arc::new(Mutex::new(HashMap::new()));
+
+        let listener_responses = responses.clone();
+        let listener = Listener::start(port, move |stream| {
+            let responses = listener_responses.lock().unwrap().clone();
+            if let Err(e) = respond(stream, &responses) {
+                eprintln!("Failed to answer HTTP challenge request: {e}");
+            }
+        })?;
+
+        Ok(Self {
+            responses,
+            _listener: listener,
+        })
+    }
+
+    /// Answer requests for the challenge `token` with the key authorization.
+    pub fn add(&self, token: &str, key_authorization: &str) -> std::io::Result<()> {
+        check_token(token)?;
+
+        self.responses.lock().unwrap()
+            .insert(token.to_string(), key_authorization.to_string());
+
+        Ok(())
+    }
+}
+
+/// Make sure the challenge token only holds base64url characters, as it ends up in a path.
+/// ([RFC 8555§8.3](https://www.rfc-editor.org/rfc/rfc8555.html#section-8.3))
+fn check_token(token: &str) -> std::io::Result<()> {
+    let is_base64url = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+    if token.is_empty() || !token.chars().all(is_base64url) {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput,
+            format!("invalid challenge token '{}'", token.escape_debug())));
+    }
+
+    Ok(())
+}
+
+/// Read a single request from the stream, answering with the matching key authorization (if any).
+fn respond(stream: TcpStream, responses: &HashMap<String, String>) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+    // Only the request line matters, e.g. "GET /.well-known/acme-challenge/<token> HTTP/1.1".
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.by_ref().take(8192).read_line(&mut request_line)?;
+
+    let mut parts = request_line.split_whitespace();
+    let key_authorization = match (parts.next(), parts.next()) {
+        (Some("GET"), Some(path)) => path.strip_prefix(CHALLENGE_PATH)
+            .and_then(|token| responses.get(token)),
+        _ => None,
+    };
+
+    // Skip the headers, so the client isn't reset before reading the response.
+    let mut line = String::new();
+    while reader.by_ref().take(8192).read_line(&mut line)? > 2 {
+        line.clear();
+    }
+
+    let response = match key_authorization {
+        Some(body) => format!("HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\n\
+            Content-Length: {}\r\nConnection: close\r\n\r\n{body}", body.len()),
+        None => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+    };
+
+    (&stream).write_all(response.as_bytes())
+}
+
+/// Write the key authorization for the challenge `token` into an existing web server's document root,
+/// returning the path of the written file.
+pub fn write_webroot(webroot: &Path, token: &str, key_authorization: &str) -> std::io::Result<PathBuf> {
+    check_token(token)?;
+
+    let directory = webroot.join(CHALLENGE_PATH.trim_matches('/'));
+    std::fs::create_dir_all(&directory)?;
+
+    let path = directory.join(token);
+    std::fs::write(&path, key_authorization)?;
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_base64url_tokens() {
+        assert!(check_token("evaGxfADs6pSRb2LAv9IZf17Dt3juxGJ-PCt92wr-oA").is_ok());
+        assert!(check_token("a_b-C9").is_ok());
+    }
+
+    #[test]
+    fn rejects_tokens_escaping_the_challenge_directory() {
+        for token in ["", "../../etc/x", "/etc/passwd", "a/b", "a\\b", "..", "tok=en", "tök"] {
+            assert!(check_token(token).is_err(), "{token:?} was accepted");
+        }
+    }
+
+    #[test]
+    fn webroot_refuses_invalid_tokens() {
+        let webroot = std::env::temp_dir().join(format!("snacme-webroot-{}", std::process::id()));
+        assert!(write_webroot(&webroot, "../escaped", "key-authorization").is_err());
+        assert!(!webroot.join(".well-known/escaped").exists());
+    }
+}
@@ -0,0 +1,219 @@
+pub mod http;
+pub mod tls_alpn;
+
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crate::api::porkbun::PorkbunAPI;
+use crate::model::Challenge;
+use crate::model::ChallengeType;
+
+/// Sets up the responses to an order's challenges, and removes them again once it's done.
+pub struct Responder<'a> {
+    dns_api: Option<&'a PorkbunAPI>,
+    http_port: u16,
+    tls_port: u16,
+
+    // (Root, Subdomain) of each name TXT DNS records were created for.
+    dns_records: Vec<(String, String)>,
+    http_server: Option<http::StandaloneServer>,
+    tls_server: Option<tls_alpn::TlsAlpnServer>,
+    webroot_files: Vec<PathBuf>,
+}
+
+impl<'a> Responder<'a> {
+    /// Use the given DNS API for 'dns-01' challenges, and the given ports for 'http-01' and
+    /// 'tls-alpn-01' challenges.
+    pub fn new(dns_api: Option<&'a PorkbunAPI>, http_port: u16, tls_port: u16) -> Self {
+        Self {
+            dns_api,
+            http_port,
+            tls_port,
+            dns_records: Vec::new(),
+            http_server: None,
+            tls_server: None,
+            webroot_files: Vec::new(),
+        }
+    }
+
+    /// Set up the response to the challenge. `root` is the root domain of the challenge's domain,
+    /// i.e. the DNS zone TXT records are created in. For 'http-01' challenges, the response is written
+    /// into `webroot` if given, and otherwise served by a built-in HTTP server. 'tls-alpn-01' challenges
+    /// are always answered by a built-in TLS server.
+    pub fn deploy(&mut self, challenge: &Challenge, root: &str, webroot: Option<&Path>) -> Result<(), String> {
+        let challenge_type = ChallengeType::try_from(challenge.r#type.as_str())
+            .map_err(|e| e.to_string())?;
+
+        match (challenge_type, webroot) {
+            (ChallengeType::DNS, _) => {
+                let dns_api = self.dns_api
+                    .ok_or(format!("A DNS API is needed for the 'dns-01' challenge of {}", challenge.domain))?;
+
+                // Wildcard domains are validated through the record of the domain below the wildcard.
+                // (RFC 8555§8.4)
+                let domain = challenge.domain.strip_prefix("*.")
+                    .unwrap_or(&challenge.domain);
+                let subdomain = match domain.strip_suffix(root) {
+                    Some(sub) if !sub.is_empty() => format!("_acme-challenge.{}", sub.trim_end_matches('.')),
+                    _ => String::from("_acme-challenge"),
+                };
+
+                // A domain and its wildcard share the record name, so both values are kept side by side.
+                dns_api.create(Some(&subdomain), root, &challenge.response)
+                    .map_err(|e| format!("Failed to create DNS TXT record for {}: {e}", challenge.domain))?;
+
+                let record = (root.to_string(), subdomain);
+                if !self.dns_records.contains(&record) {
+                    self.dns_records.push(record);
+                }
+            },
+
+            (ChallengeType::Http, Some(webroot)) => {
+                let path = http::write_webroot(webroot, &challenge.token, &challenge.response)
+                    .map_err(|e| format!("Failed to write challenge file into {}: {e}", webroot.display()))?;
+
+                self.webroot_files.push(path);
+            },
+
+            (ChallengeType::Http, None) => {
+                if self.http_server.is_none() {
+                    self.http_server = Some(http::StandaloneServer::start(self.http_port)
+                        .map_err(|e| format!("Failed to listen on port {}: {e}", self.http_port))?);
+                }
+
+                if let Some(ref server) = self.http_server {
+                    server.add(&challenge.token, &challenge.response)
+                        .map_err(|e| format!("Failed to answer the challenge for {}: {e}", challenge.domain))?;
+                }
+            },
+
+            (ChallengeType::TlsAlpn, _) => {
+                if self.tls_server.is_none() {
+                    self.tls_server = Some(tls_alpn::TlsAlpnServer::start(self.tls_port)
+                        .map_err(|e| format!("Failed to listen on port {}: {e}", self.tls_port))?);
+                }
+
+                if let Some(ref server) = self.tls_server {
+                    server.add(&challenge.domain, &challenge.response)
+                        .map_err(|e| format!("Failed to create validation certificate for {}: {e}", challenge.domain))?;
+                }
+            },
+        }
+
+        Ok(())
+    }
+
+    /// Whether any DNS records were created, which need some time to propagate.
+    pub fn created_dns_records(&self) -> bool {
+        !self.dns_records.is_empty()
+    }
+
+    /// Remove the responses to every challenge set up so far. Every removal is attempted, even after
+    /// others failed, so one failure doesn't leave the rest behind.
+    pub fn cleanup(&mut self) -> Result<(), String> {
+        self.http_server = None;
+        self.tls_server = None;
+
+        let mut errors = Vec::new();
+        for path in self.webroot_files.drain(..) {
+            match std::fs::remove_file(&path) {
+                // E.g. already cleaned up by the web server.
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+                Err(e) => errors.push(format!("Failed to remove challenge file {}: {e}", path.display())),
+                Ok(()) => (),
+            }
+        }
+
+        if let Some(dns_api) = self.dns_api {
+            for (root, sub) in self.dns_records.drain(..) {
+                if let Err(e) = dns_api.delete(Some(&sub), &root) {
+                    errors.push(format!("Failed to delete DNS TXT record for {root}: {e}"));
+                }
+            }
+        }
+
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(errors.join("; ")),
+        }
+    }
+}
+
+/// Accepts connections on a port in a background thread, handing them to a handler one at a time,
+/// until dropped.
+pub(crate) struct Listener {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Listener {
+    /// Start listening on the given port, on all addresses.
+    pub(crate) fn start<F: Fn(TcpStream) + Send + 'static>(port: u16, handler: F) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(("::", port))
+            .or_else(|_| TcpListener::bind(("0.0.0.0", port)))?;
+        listener.set_nonblocking(true)?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread = std::thread::spawn(move || {
+            while !thread_stop.load(Ordering::Relaxed) {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        if stream.set_nonblocking(false).is_ok() {
+                            handler(stream);
+                        }
+                    },
+
+                    // Nothing to accept yet, check whether to stop in a little bit.
+                    Err(_) => std::thread::sleep(Duration::from_millis(50)),
+                }
+            }
+        });
+
+        Ok(Self {
+            stop,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cleanup_removes_every_file_despite_failures() {
+        let directory = std::env::temp_dir().join(format!("snacme-cleanup-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(directory.join("unremovable")).unwrap();
+        std::fs::write(directory.join("first"), "").unwrap();
+        std::fs::write(directory.join("last"), "").unwrap();
+
+        let mut responder = Responder::new(None, 80, 443);
+        responder.webroot_files = ["first", "already-removed", "unremovable", "last"].iter()
+            .map(|name| directory.join(name))
+            .collect();
+
+        let error = responder.cleanup().unwrap_err();
+        assert!(error.contains("unremovable") && !error.contains("already-removed"), "{error}");
+        assert!(!directory.join("first").exists());
+        assert!(!directory.join("last").exists());
+        assert!(responder.webroot_files.is_empty());
+
+        let _ = std::fs::remove_dir_all(&directory);
+    }
+}
@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rcgen::{Certificate, CertificateParams, CustomExtension, DistinguishedName, SanType};
+use rustls::crypto::ring::sign::any_supported_type;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::{ServerConfig, ServerConnection};
+use sha2::{Digest, Sha256};
+
+use super::Listener;
+
+/// The ALPN protocol validation servers ask for. ([RFC 8737§6.2](https://www.rfc-editor.org/rfc/rfc8737.html#section-6.2))
+const ACME_TLS_ALPN: &[u8] = b"acme-tls/1";
+
+/// Picks the validation certificate matching the requested server name.
+#[derive(Debug, Default)]
+struct CertificateResolver {
+    certificates: Mutex<HashMap<String, Arc<CertifiedKey>>>,
+}
+
+impl ResolvesServerCert for CertificateResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        let server_name = client_hello.server_name()?;
+
+        self.certificates.lock().unwrap()
+            .get(server_name)
+            .cloned()
+    }
+}
+
+/// A temporary TLS server answering 'tls-alpn-01' challenges for as long as it's alive.
+/// ([RFC 8737](https://www.rfc-editor.org/rfc/rfc8737.html))
+pub struct TlsAlpnServer {
+    resolver: Arc<CertificateResolver>,
+    _listener: Listener,
+}
+
+impl TlsAlpnServer {
+    /// Start listening on the given port, on all addresses.
+    pub fn start(port: u16) -> Result<Self, String> {
+        let resolver = Arc::new(CertificateResolver::default());
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut tls_config = ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(|e| e.to_string())?
+            .with_no_client_auth()
+            .with_cert_resolver(resolver.clone());
+        tls_config.alpn_protocols = vec![ACME_TLS_ALPN.to_vec()];
+
+        let tls_config = Arc::new(tls_config);
+        let listener = Listener::start(port, move |stream| {
+            if let Err(e) = respond(stream, tls_config.clone()) {
+                eprintln!("Failed to answer TLS-ALPN challenge request: {e}");
+            }
+        }).map_err(|e| e.to_string())?;
+
+        Ok(Self {
+            resolver,
+            _listener: listener,
+        })
+    }
+
+    /// Answer requests for `domain` (or IP address) with a validation certificate for the key authorization.
+    pub fn add(&self, domain: &str, key_authorization: &str) -> Result<(), String> {
+        let certified_key = validation_certificate(domain, key_authorization)?;
+
+        // IP addresses are requested by their reverse DNS name. (RFC 8738§6)
+        let server_name = match domain.parse::<IpAddr>() {
+            Ok(ip) => reverse_dns_name(ip),
+            Err(_) => domain.to_string(),
+        };
+
+        self.resolver.certificates.lock().unwrap()
+            .insert(server_name, Arc::new(certified_key));
+
+        Ok(())
+    }
+}
+
+/// Generate the self-signed validation certificate for `domain`, carrying the SHA-256 digest of the
+/// key authorization in the critical `id-pe-acmeIdentifier` extension.
+/// ([RFC 8737§3](https://www.rfc-editor.org/rfc/rfc8737.html#section-3))
+fn validation_certificate(domain: &str, key_authorization: &str) -> Result<CertifiedKey, String> {
+    let mut cert_params = CertificateParams::default();
+    cert_params.subject_alt_names = vec![match domain.parse::<IpAddr>() {
+        Ok(ip) => SanType::IpAddress(ip),
+        Err(_) => SanType::DnsName(domain.to_string()),
+    }];
+    cert_params.distinguished_name = DistinguishedName::new();
+    cert_params.custom_extensions = vec![
+        CustomExtension::new_acme_identifier(&Sha256::digest(key_authorization)),
+    ];
+
+    let cert = Certificate::from_params(cert_params)
+        .map_err(|e| e.to_string())?;
+    let cert_der = cert.serialize_der()
+        .map_err(|e| e.to_string())?;
+    let key_der = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(cert.serialize_private_key_der()));
+    let signing_key = any_supported_type(&key_der)
+        .map_err(|e| e.to_string())?;
+
+    Ok(CertifiedKey::new(vec![CertificateDer::from(cert_der)], signing_key))
+}
+
+/// The name under the "in-addr.arpa" or "ip6.arpa" domain that the IP address is looked up as.
+/// ([RFC 1035§3.5](https://www.rfc-editor.org/rfc/rfc1035.html#section-3.5),
+/// [RFC 3596§2.5](https://www.rfc-editor.org/rfc/rfc3596.html#section-2.5))
+fn reverse_dns_name(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, d] = ip.octets();
+            format!("{d}.{c}.{b}.{a}.in-addr.arpa")
+        },
+
+        IpAddr::V6(ip) => {
+            let nibbles: Vec<String> = ip.octets().iter().rev()
+                .flat_map(|byte| [byte & 0xf, byte >> 4])
+                .map(|nibble| format!("{nibble:x}"))
+                .collect();
+            format!("{}.ip6.arpa", nibbles.join("."))
+        },
+    }
+}
+
+/// Complete a TLS handshake on the stream, presenting the matching validation certificate.
+fn respond(mut stream: TcpStream, tls_config: Arc<ServerConfig>) -> Result<(), String> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))
+        .map_err(|e| e.to_string())?;
+
+    let mut connection = ServerConnection::new(tls_config)
+        .map_err(|e| e.to_string())?;
+    while connection.is_handshaking() {
+        connection.complete_io(&mut stream)
+            .map_err(|e| e.to_string())?;
+    }
+
+    // Nothing else is served, the validation server only needs the handshake.
+    connection.send_close_notify();
+    connection.complete_io(&mut stream)
+        .map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reverse_dns_name_of_ipv4() {
+        assert_eq!(reverse_dns_name("192.0.2.1".parse().unwrap()), "1.2.0.192.in-addr.arpa");
+        assert_eq!(reverse_dns_name("10.0.0.255".parse().unwrap()), "255.0.0.10.in-addr.arpa");
+    }
+
+    #[test]
+    fn reverse_dns_name_of_ipv6() {
+        // RFC 3596§2.5's example, every nibble written out in reverse.
+        assert_eq!(reverse_dns_name("4321:0:1:2:3:4:567:89ab".parse().unwrap()),
+            "b.a.9.8.7.6.5.0.4.0.0.0.3.0.0.0.2.0.0.0.1.0.0.0.0.0.0.0.1.2.3.4.ip6.arpa");
+        assert_eq!(reverse_dns_name("::1".parse().unwrap()),
+            format!("1{}.ip6.arpa", ".0".repeat(31)));
+    }
+
+    #[test]
+    fn reverse_dns_name_of_ipv4_mapped_ipv6() {
+        // Mapped addresses stay IPv6, they aren't looked up under "in-addr.arpa".
+        let name = reverse_dns_name("::ffff:192.0.2.1".parse().unwrap());
+        assert!(name.starts_with("1.0.2.0.0.0.0.c.f.f.f.f."));
+        assert!(name.ends_with(".ip6.arpa"));
+        assert_eq!(name.split('.').count(), 34);
+    }
+}
@@ -1,9 +1,37 @@
 use serde::Deserialize;
 
+use std::time::Duration;
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+
+use crate::model::{CertificateAuthority, ChallengeType, ExternalAccountBinding, KeyType};
+use crate::model::order::{CsrOptions, OrderOptions};
+use crate::model::poller::Poller;
+use crate::store::{Output, OutputOptions, DEFAULT_OUTPUTS};
+
+/// A single challenge type, or several in order of preference.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Challenges {
+    One(ChallengeType),
+    Many(Vec<ChallengeType>),
+}
+
+impl Challenges {
+    pub fn as_slice(&self) -> &[ChallengeType] {
+        match self {
+            Self::One(challenge) => std::slice::from_ref(challenge),
+            Self::Many(challenges) => challenges,
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct DomainRequest {
     pub root: String,
     pub hosts: Option<Vec<String>>,
+    pub challenge: Option<Challenges>,
+    pub webroot: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -11,6 +39,120 @@ pub struct CertificateRequest {
     pub name: String,
     #[serde(alias = "domain")]
     pub domains: Vec<DomainRequest>,
+    pub challenge: Option<Challenges>,
+    pub webroot: Option<String>,
+    #[serde(default)]
+    pub key_type: KeyType,
+    #[serde(default)]
+    pub reuse_key: bool,
+    pub key: Option<String>,
+    pub csr: Option<String>,
+    #[serde(default)]
+    pub must_staple: bool,
+    pub common_name: Option<String>,
+    pub profile: Option<String>,
+    pub not_before: Option<String>,
+    pub not_after: Option<String>,
+    pub outputs: Option<Vec<Output>>,
+    pub pkcs12_password: Option<String>,
+    pub owner: Option<String>,
+    pub group: Option<String>,
+}
+
+impl CertificateRequest {
+    /// The [OrderOptions] for this certificate, besides the certificate it replaces.
+    pub fn order_options(&self) -> Result<OrderOptions<'_>, String> {
+        Ok(OrderOptions {
+            replaces: None,
+            profile: self.profile.as_deref(),
+            not_before: self.not_before.as_deref().map(order_time).transpose()?,
+            not_after: self.not_after.as_deref().map(order_time).transpose()?,
+        })
+    }
+
+    /// The [CsrOptions] for this certificate, which can't be combined with an external CSR.
+    pub fn csr_options(&self) -> Result<CsrOptions<'_>, String> {
+        if self.csr.is_some() && (self.must_staple || self.common_name.is_some()) {
+            return Err("'must_staple' and 'common_name' can't be used with an external 'csr'".to_string());
+        }
+
+        Ok(CsrOptions {
+            must_staple: self.must_staple,
+            common_name: self.common_name.as_deref(),
+        })
+    }
+}
+
+/// Parse an RFC 3339 timestamp, or a number of days ("6d") or hours ("12h") from now.
+fn order_time(value: &str) -> Result<OffsetDateTime, String> {
+    let relative = |suffix, unit: fn(i64) -> time::Duration| value.strip_suffix(suffix)
+        .and_then(|amount| amount.trim().parse::<i64>().ok())
+        .map(|amount| OffsetDateTime::now_utc() + unit(amount));
+
+    relative('d', time::Duration::days)
+        .or_else(|| relative('h', time::Duration::hours))
+        .map_or_else(|| OffsetDateTime::parse(value, &Rfc3339), Ok)
+        .map_err(|e| format!("Invalid time '{value}': {e}"))
+}
+
+/// The ID of the user with the given name, or the ID itself if numeric.
+#[cfg(unix)]
+fn user_id(user: &str) -> Result<u32, String> {
+    if let Ok(uid) = user.parse() {
+        return Ok(uid);
+    }
+
+    let name = std::ffi::CString::new(user)
+        .map_err(|e| e.to_string())?;
+    // SAFETY: an all-zero `passwd` is valid, and only filled in by `getpwnam_r`.
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0; 16384];
+    let mut result = std::ptr::null_mut();
+
+    // SAFETY: every pointer is valid for the duration of the call, and the buffer's length is passed along.
+    let status = unsafe {
+        libc::getpwnam_r(name.as_ptr(), &mut passwd, buffer.as_mut_ptr(), buffer.len(), &mut result)
+    };
+    match (status, result.is_null()) {
+        (0, false) => Ok(passwd.pw_uid),
+        (0, true) => Err(format!("Unknown user '{user}'")),
+        (errno, _) => Err(format!("Failed to look up user '{user}': {}", std::io::Error::from_raw_os_error(errno))),
+    }
+}
+
+/// The ID of the group with the given name, or the ID itself if numeric.
+#[cfg(unix)]
+fn group_id(group: &str) -> Result<u32, String> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+
+    let name = std::ffi::CString::new(group)
+        .map_err(|e| e.to_string())?;
+    // SAFETY: an all-zero `group` is valid, and only filled in by `getgrnam_r`.
+    let mut entry: libc::group = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0; 16384];
+    let mut result = std::ptr::null_mut();
+
+    // SAFETY: every pointer is valid for the duration of the call, and the buffer's length is passed along.
+    let status = unsafe {
+        libc::getgrnam_r(name.as_ptr(), &mut entry, buffer.as_mut_ptr(), buffer.len(), &mut result)
+    };
+    match (status, result.is_null()) {
+        (0, false) => Ok(entry.gr_gid),
+        (0, true) => Err(format!("Unknown group '{group}'")),
+        (errno, _) => Err(format!("Failed to look up group '{group}': {}", std::io::Error::from_raw_os_error(errno))),
+    }
+}
+
+#[cfg(not(unix))]
+fn user_id(_user: &str) -> Result<u32, String> {
+    Err("'owner' is only supported on Unix".to_string())
+}
+
+#[cfg(not(unix))]
+fn group_id(_group: &str) -> Result<u32, String> {
+    Err("'group' is only supported on Unix".to_string())
 }
 
 #[derive(Debug, Deserialize)]
@@ -19,6 +161,14 @@ pub struct PorkbunKeys {
     pub secret: String,
 }
 
+/// How long to wait for orders to complete, and how often to check on them (in seconds).
+#[derive(Debug, Default, Deserialize)]
+pub struct Polling {
+    pub timeout: Option<u64>,
+    pub interval: Option<u64>,
+    pub max_interval: Option<u64>,
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum DNSRecordsAPI {
@@ -34,9 +184,143 @@ pub enum DNSRecordsAPI {
 pub struct Config {
     #[serde(alias = "directory")]
     pub output_directory: String,
+    #[serde(alias = "accounts")]
+    pub account_directory: Option<String>,
+    #[serde(alias = "contacts", alias = "email")]
+    pub contact: Option<Vec<String>>,
+    #[serde(alias = "tos")]
+    pub terms_of_service: Option<String>,
+    #[serde(alias = "eab")]
+    pub external_account_binding: Option<ExternalAccountBinding>,
     pub staging: Option<bool>,
+    #[serde(alias = "ca")]
+    pub certificate_authority: Option<String>,
+    pub ca_certificates: Option<String>,
+    pub preferred_chain: Option<String>,
     #[serde(alias = "api")]
-    pub dns_api: DNSRecordsAPI,
+    pub dns_api: Option<DNSRecordsAPI>,
+    pub http_port: Option<u16>,
+    pub tls_port: Option<u16>,
+    #[serde(default)]
+    pub polling: Polling,
+    /// How many days before expiry to renew certificates.
+    pub renew_before_days: Option<u64>,
+    pub outputs: Option<Vec<Output>>,
+    pub pkcs12_password: Option<String>,
+    pub owner: Option<String>,
+    pub group: Option<String>,
+    /// Whether to keep every issued version of a certificate, with `live/<name>` linking to the one in use.
+    #[serde(default)]
+    pub archive: bool,
     #[serde(alias = "certificate")]
     pub certs: Vec<CertificateRequest>,
-}
\ No newline at end of file
+}
+impl Config {
+    /// The Certificate Authority to use; a preset name (using its staging endpoint if `staging`
+    /// is set) or the URL of any other ACME directory. Defaults to Let's Encrypt.
+    pub fn certificate_authority(&self) -> Result<CertificateAuthority, String> {
+        let staging = self.staging.unwrap_or(false);
+        let name = self.certificate_authority.as_deref()
+            .unwrap_or("letsencrypt");
+
+        match (name, staging) {
+            ("letsencrypt", true) => Ok(CertificateAuthority::LetsEncryptStaging),
+            ("letsencrypt", false) => Ok(CertificateAuthority::LetsEncryptProduction),
+            ("buypass", true) => Ok(CertificateAuthority::BuypassStaging),
+            ("buypass", false) => Ok(CertificateAuthority::BuypassProduction),
+            ("google", true) => Ok(CertificateAuthority::GoogleStaging),
+            ("google", false) => Ok(CertificateAuthority::GoogleProduction),
+            ("zerossl" | "sslcom" | "sslcom-ecc", true) => Err(format!("'{name}' has no staging endpoint")),
+            ("zerossl", false) => Ok(CertificateAuthority::ZeroSsl),
+            ("sslcom", false) => Ok(CertificateAuthority::SslComRsa),
+            ("sslcom-ecc", false) => Ok(CertificateAuthority::SslComEcc),
+            (url, _) if url.starts_with("https://") => Ok(CertificateAuthority::Custom(url.to_string())),
+            _ => Err(format!("Unknown certificate authority '{name}'")),
+        }
+    }
+
+    /// A [Poller] for waiting on an order, by default checking every 5 seconds (growing by 5 seconds
+    /// each time, up to a minute) for at most 5 minutes.
+    pub fn poller(&self) -> Poller {
+        let interval = self.polling.interval.unwrap_or(5);
+
+        Poller::new(Duration::from_secs(self.polling.timeout.unwrap_or(300)),
+            Duration::from_secs(interval),
+            Duration::from_secs(self.polling.max_interval.unwrap_or(60).max(interval)))
+    }
+
+    /// How long before expiry to renew certificates, if configured.
+    pub fn renew_before(&self) -> Option<time::Duration> {
+        self.renew_before_days
+            .map(|days| time::Duration::days(days as i64))
+    }
+
+    /// How to write the files of the named certificate; as configured for the certificate, or
+    /// otherwise for all certificates.
+    pub fn output_options(&self, cert_name: &str) -> Result<OutputOptions<'_>, String> {
+        let cert_request = self.cert(cert_name);
+        let owner = cert_request.and_then(|cert_request| cert_request.owner.as_deref())
+            .or(self.owner.as_deref());
+        let group = cert_request.and_then(|cert_request| cert_request.group.as_deref())
+            .or(self.group.as_deref());
+
+        Ok(OutputOptions {
+            outputs: cert_request.and_then(|cert_request| cert_request.outputs.as_deref())
+                .or(self.outputs.as_deref())
+                .unwrap_or(DEFAULT_OUTPUTS),
+            pkcs12_password: cert_request.and_then(|cert_request| cert_request.pkcs12_password.as_deref())
+                .or(self.pkcs12_password.as_deref()),
+            owner: owner.map(user_id).transpose()?,
+            group: group.map(group_id).transpose()?,
+        })
+    }
+
+    fn cert(&self, cert_name: &str) -> Option<&CertificateRequest> {
+        self.certs.iter()
+            .find(|cert_request| cert_request.name == cert_name)
+    }
+
+    /// Account contact URLs, with "mailto:" prepended to plain email addresses.
+    pub fn contacts(&self) -> Vec<String> {
+        self.contact.iter()
+            .flatten()
+            .map(|contact| match contact.contains(':') {
+                true => contact.clone(),
+                false => format!("mailto:{contact}"),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(time: OffsetDateTime, expected: OffsetDateTime) {
+        assert!((time - expected).abs() < time::Duration::seconds(5), "{time} is not close to {expected}");
+    }
+
+    #[test]
+    fn order_time_parses_relative_days_and_hours() {
+        let now = OffsetDateTime::now_utc();
+
+        assert_close(order_time("6d").unwrap(), now + time::Duration::days(6));
+        assert_close(order_time("12h").unwrap(), now + time::Duration::hours(12));
+        assert_close(order_time("0h").unwrap(), now);
+        assert_close(order_time(" 3 d").unwrap(), now + time::Duration::days(3));
+    }
+
+    #[test]
+    fn order_time_parses_rfc3339_timestamps() {
+        assert_eq!(order_time("2025-06-01T12:00:00Z").unwrap().unix_timestamp(), 1748779200);
+        assert_eq!(order_time("2025-06-01T14:00:00+02:00").unwrap().unix_timestamp(), 1748779200);
+    }
+
+    #[test]
+    fn order_time_rejects_anything_else() {
+        for value in ["", "d", "h", "6", "6w", "1.5d", "6dd", "2025-06-01", "2025-13-01T00:00:00Z", "tomorrow"] {
+            let error = order_time(value).unwrap_err();
+            assert!(error.contains(&format!("'{value}'")), "{error}");
+        }
+    }
+}
@@ -1,16 +1,41 @@
 mod api;
+mod challenge;
 mod config;
 mod model;
+mod store;
 
-use std::path::PathBuf;
-use std::time::{Duration, Instant};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
 
 use api::porkbun::PorkbunAPI;
-use config::{CertificateRequest, Config, DNSRecordsAPI, DomainRequest};
+use challenge::Responder;
+use config::{CertificateRequest, Challenges, Config, DNSRecordsAPI, DomainRequest};
 use model::account::Account;
 use model::authorization::AuthStatus;
+use model::key::CertificateKey;
 use model::{CertificateAuthority, ChallengeType};
 use model::order::OrderStatus;
+use model::renewal::IssuedCertificate;
+use model::revocation::{revoke_with_certificate_key, RevocationReason};
+use store::{AccountStore, CertificateStore, OutputOptions};
+
+/// Exit with a readable error message instead of panicking with a debug dump of the error.
+trait OrExit<T> {
+    fn or_exit(self, context: &str) -> T;
+}
+
+impl<T, E: std::fmt::Display> OrExit<T> for Result<T, E> {
+    fn or_exit(self, context: &str) -> T {
+        self.unwrap_or_else(|e| {
+            eprintln!("{context}: {e}");
+            std::process::exit(1);
+        })
+    }
+}
 
 /// Attempt to convert the argument at `index` to a [PathBuf].
 fn arg_as_path(index: usize) -> Option<PathBuf> {
@@ -40,39 +65,51 @@ fn get_config() -> Result<Config, String> {
     }
 }
 
-/// Load an account file from the path specified by the binary's second argument.
-/// If that fails for some reason, attempt to just generate an account.
-fn get_account(authority: CertificateAuthority) -> Result<Account, String> {
-    if let Some(path) = arg_as_path(2) {
-        let account_bytes = std::fs::read(path)
-            .map_err(|e| e.to_string())?;
+/// The challenge type used when none is configured.
+const DEFAULT_CHALLENGES: &[ChallengeType] = &[ChallengeType::DNS];
 
-        return Account::try_from(account_bytes.as_slice())
-            .map_err(|e| format!("{:?}", e));
-    } else {
-        return Account::generate(authority)
-            .map_err(|e| format!("{:?}", e));
-    }
+/// The challenge types used for IP addresses when none are configured, as they can't be validated
+/// through DNS. ([RFC 8738§7](https://www.rfc-editor.org/rfc/rfc8738.html#section-7))
+const DEFAULT_IP_CHALLENGES: &[ChallengeType] = &[ChallengeType::Http, ChallengeType::TlsAlpn];
+
+/// A single domain to be included in a certificate.
+struct RequestedDomain<'a> {
+    name: String,
+    root: &'a str,
+    challenges: &'a [ChallengeType],
+    webroot: Option<&'a Path>,
 }
 
 /// Convert a vector of [CertificateRequest]s to a simpler form.
-fn convert_requests(requests: &Vec<CertificateRequest>) -> Vec<(&str, Vec<(String, usize)>)> {
+fn convert_requests(requests: &[CertificateRequest]) -> Vec<(&str, Vec<RequestedDomain<'_>>)> {
     let mut converted = Vec::new();
 
     for cert_request in requests {
         let mut domains = Vec::new();
 
-        for DomainRequest { root, hosts } in &cert_request.domains {
-            if let Some(hosts) = hosts {
+        for DomainRequest { root, hosts, challenge, webroot } in &cert_request.domains {
+            let ip = root.parse::<IpAddr>().ok();
+            let challenges = challenge.as_ref()
+                .or(cert_request.challenge.as_ref())
+                .map_or(if ip.is_some() { DEFAULT_IP_CHALLENGES } else { DEFAULT_CHALLENGES }, Challenges::as_slice);
+            let webroot = webroot.as_ref()
+                .or(cert_request.webroot.as_ref())
+                .map(Path::new);
+            let requested = |name| RequestedDomain { name, root: root.as_str(), challenges, webroot };
+
+            // IP addresses have no subdomains, and are written the way the server will report them.
+            if let Some(ip) = ip {
+                domains.push(requested(ip.to_string()));
+            } else if let Some(hosts) = hosts {
                 domains.extend(hosts.iter().map(|sub| {
                     if sub == "." {
-                        (root.clone(), 0)
+                        requested(root.clone())
                     } else {
-                        (format!("{sub}.{root}"), sub.len())
+                        requested(format!("{sub}.{root}"))
                     }
                 }));
             } else {
-                domains.push((root.clone(), 0));
+                domains.push(requested(root.clone()));
             }
         }
 
@@ -82,165 +119,555 @@ fn convert_requests(requests: &Vec<CertificateRequest>) -> Vec<(&str, Vec<(Strin
     converted
 }
 
-fn main() {
-    let config: Config = get_config()
-        .expect("Failed to load configuration file");
+/// Read the DER encoded data in the file, or the first PEM block with the given label if it's PEM encoded.
+fn read_der_or_pem(path: &Path, label: &str) -> Result<Vec<u8>, String> {
+    let data = std::fs::read(path)
+        .map_err(|e| format!("{}: {e}", path.display()))?;
+    if !data.starts_with(b"-----BEGIN") {
+        return Ok(data);
+    }
 
-    // Convert the requested certificates into easier to work with forms.
-    //   cert_requests: Certificates<Domains<(Domain, SubdomainSplitIndex)>>
-    //   split_requests: Certificates<Domains<(Root, Option<Subdomain>)>>
-    let cert_requests: Vec<(&str, Vec<(String, usize)>)> = convert_requests(&config.certs);
-    let cert_map: Vec<Vec<(&str, String)>> = cert_requests.iter()
-        .map(|(_, request)| {
-            request.iter()
-                .map(|(domain, sub_index)| {
-                    let mut subdomain = String::from("_acme-challenge");
-                    if *sub_index == 0 {
-                        (domain.as_str(), subdomain)
-                    } else {
-                        let (sub, root) = domain.split_at(*sub_index);
-                        subdomain.push_str(&format!(".{sub}"));
+    let pem = String::from_utf8(data)
+        .map_err(|e| format!("{}: {e}", path.display()))?;
+    model::decode_pem(&pem, label)
+        .map_err(|e| format!("{}: {e}", path.display()))?
+        .into_iter()
+        .next()
+        .ok_or(format!("{} contains no {label}", path.display()))
+}
 
-                        (root.strip_prefix('.').unwrap(), subdomain)
-                    }
-                })
-                .collect()
-        })
-        .collect();
-    
-    // Load the DNS records API to use for this configuration.
-    let dns_api = match config.dns_api {
-        DNSRecordsAPI::Porkbun { keys } => PorkbunAPI::new(keys.secret, keys.public),
-        _ => unimplemented!("Specified DNS API is currently unimplemented!")
+/// The key to request the certificate for: an external CSR or key file, the key of the certificate
+/// from a previous run (unless it's no longer of the configured type), or a newly generated key.
+fn certificate_key(config: &Config, store: &CertificateStore, cert_request: &CertificateRequest) -> Result<CertificateKey, String> {
+    match (&cert_request.csr, &cert_request.key, cert_request.reuse_key) {
+        (Some(csr), None, false) => read_der_or_pem(Path::new(csr), "CERTIFICATE REQUEST")
+            .map(CertificateKey::Csr),
+        (None, Some(key), false) => read_der_or_pem(Path::new(key), "PRIVATE KEY")
+            .map(CertificateKey::Existing),
+        (None, None, true) => Ok(match store.load_key(&cert_request.name, &config.output_options(&cert_request.name)?)? {
+            Some(key_der) if cert_request.key_type.matches(&key_der) => CertificateKey::Existing(key_der),
+            Some(_) => {
+                println!("Stored key of certificate '{}' is not a {} key, generating a new one...",
+                    cert_request.name, cert_request.key_type);
+                CertificateKey::Generate(cert_request.key_type)
+            },
+            None => CertificateKey::Generate(cert_request.key_type),
+        }),
+        (None, None, false) => Ok(CertificateKey::Generate(cert_request.key_type)),
+        _ => Err("Only one of 'csr', 'key' and 'reuse_key' can be set".to_string()),
+    }
+}
+
+/// Read the certificate previously written to one of its outputs in the output directory, if any.
+fn existing_certificate(store: &CertificateStore, name: &str, options: &OutputOptions) -> Option<Vec<u8>> {
+    store.load_certificate(name, options)
+        .map_err(|e| eprintln!("Failed to read the existing '{name}' certificate: {e}"))
+        .ok()
+        .flatten()
+}
+
+/// When to renew the previously issued certificate: at the time within the window the Certificate
+/// Authority suggests, or once it's close to expiring (see [IssuedCertificate::expiry_renewal_time]),
+/// whichever comes first.
+fn renewal_time(config: &Config, account: &Account, store: &CertificateStore, cert_name: &str,
+    issued: &IssuedCertificate) -> OffsetDateTime {
+    let expiry_renewal_time = issued.expiry_renewal_time(config.renew_before());
+
+    let Some(ref cert_id) = issued.id else {
+        return expiry_renewal_time;
     };
 
-    // Generate/load an account.
-    let mut account = match config.staging.unwrap_or(false) {
-        true => get_account(CertificateAuthority::LetsEncryptStaging)
-            .expect("Failed to generate/load staging account"),
-        false => get_account(CertificateAuthority::LetsEncryptProduction)
-            .expect("Failed to generate/load production account"),
+    // Reuse the renewal information from an earlier run until the server's `Retry-After` passes.
+    // ([RFC 9773§4.3](https://www.rfc-editor.org/rfc/rfc9773.html#section-4.3))
+    let stored = store.load_renewal_info(cert_name)
+        .filter(|renewal_info| renewal_info.is_current(cert_id));
+    let renewal_info = match stored {
+        Some(renewal_info) => renewal_info,
+        None => match account.renewal_info(cert_id) {
+            Ok(Some(renewal_info)) => {
+                if let Err(e) = store.save_renewal_info(cert_name, &renewal_info) {
+                    eprintln!("Failed to store renewal information for '{cert_name}': {e}");
+                }
+
+                renewal_info
+            },
+            Ok(None) => return expiry_renewal_time,
+            Err(e) => {
+                eprintln!("Failed to retrieve renewal information for '{cert_name}': {e}");
+                return expiry_renewal_time;
+            },
+        },
     };
 
+    match renewal_info.renewal_time() {
+        Ok(renewal_time) if renewal_time < expiry_renewal_time => {
+            if renewal_time <= OffsetDateTime::now_utc() {
+                if let Some(ref url) = renewal_info.explanation_url {
+                    println!("Certificate '{cert_name}' is due for renewal, see {url}");
+                }
+            }
+
+            renewal_time
+        },
+
+        Ok(_) => expiry_renewal_time,
+
+        Err(e) => {
+            eprintln!("Failed to read renewal information for '{cert_name}': {e}");
+            expiry_renewal_time
+        },
+    }
+}
+
+/// Request (or renew) every certificate specified in the configuration.
+fn request_certificates(config: &Config, account: &mut Account) {
+    // Keep the account's contact URLs in line with the configuration, if it lists any.
+    let contacts = config.contacts();
+    if config.contact.is_some() && account.contacts() != contacts.as_slice() {
+        println!("Updating account contacts to {}...", match contacts.is_empty() {
+            true => "none".to_string(),
+            false => contacts.join(", "),
+        });
+
+        account.update_contacts(&contacts)
+            .or_exit("Failed to update account contacts");
+    }
+
+    // Convert the requested certificates into easier to work with forms.
+    let cert_requests: Vec<(&str, Vec<RequestedDomain>)> = convert_requests(&config.certs);
+
+    // Load the DNS records API to use for this configuration, if any.
+    let dns_api = match &config.dns_api {
+        Some(DNSRecordsAPI::Porkbun { keys }) => Some(PorkbunAPI::new(keys.secret.clone(), keys.public.clone())),
+        Some(_) => unimplemented!("Specified DNS API is currently unimplemented!"),
+        None => None,
+    };
+
+    let store = CertificateStore::new(PathBuf::from(&config.output_directory), config.archive);
+
     // For each requested certificate...
-    for (cert_index, (cert_name, requested_domains)) in cert_requests.iter().enumerate() {
+    for ((cert_name, requested_domains), cert_request) in cert_requests.iter().zip(&config.certs) {
         // Collect the domains needed for the order.
         let domains: Vec<&str> = requested_domains.iter()
-            .map(|(domain, _)| domain.as_str())
+            .map(|domain| domain.name.as_str())
             .collect();
 
-        // Create the order, associated with the previously created account.
-        let mut order = account.create_order(&domains)
-            .expect("Failed to create an order");
+        // Renew the certificate from a previous run only if it's due, or no longer matches the configuration.
+        let output_options = config.output_options(cert_name)
+            .or_exit(&format!("Invalid output options for '{cert_name}'"));
+        let issued = existing_certificate(&store, cert_name, &output_options)
+            .and_then(|cert_der| IssuedCertificate::try_from(cert_der.as_slice())
+                .map_err(|e| eprintln!("Failed to read the existing '{cert_name}' certificate: {e}"))
+                .ok());
+        let mut options = cert_request.order_options()
+            .or_exit(&format!("Invalid order options for '{cert_name}'"));
+        let csr_options = cert_request.csr_options()
+            .or_exit(&format!("Invalid order options for '{cert_name}'"));
+        if let Some(ref issued) = issued {
+            if issued.has_names(&domains) {
+                let renewal_time = renewal_time(config, account, &store, cert_name, issued);
+                if renewal_time > OffsetDateTime::now_utc() {
+                    let renewal_time = renewal_time.format(&Rfc3339)
+                        .unwrap_or_else(|_| renewal_time.to_string());
+                    println!("Certificate '{cert_name}' is not due for renewal until {renewal_time}.");
+
+                    continue;
+                }
+
+                options.replaces = issued.id.as_deref();
+            } else {
+                println!("Domains of certificate '{cert_name}' changed, renewing...");
+            }
+        }
+
+        // Make sure an external CSR is usable before going through the challenges.
+        let key = certificate_key(config, &store, cert_request)
+            .and_then(|key| key.check_names(&domains).map(|_| key).map_err(|e| e.to_string()))
+            .or_exit(&format!("Invalid key for '{cert_name}'"));
+
+        // The challenge types that already failed for a domain, which later orders fall back from.
+        let mut failed: Vec<(String, ChallengeType)> = Vec::new();
+        let preferences = |failed: &[(String, ChallengeType)], identifier: &str| -> Vec<ChallengeType> {
+            requested_domains.iter()
+                .find(|domain| domain.name == identifier)
+                .map_or(DEFAULT_CHALLENGES, |domain| domain.challenges)
+                .iter()
+                .filter(|ct| !failed.iter().any(|(name, failed_ct)| name == identifier && failed_ct == *ct))
+                .copied()
+                .collect()
+        };
 
-        // Retrieve authorizations for the order.
-        let authorizations = order.authorize(ChallengeType::DNS)
-            .expect("Failed to retrieve order authorizations");
+        loop {
+            // Create the order, associated with the previously created account.
+            let mut order = account.create_order(&domains, &options)
+                .or_exit("Failed to create an order");
 
-        // Create the necessary TXT DNS records.
-        for authorization in authorizations.iter() {
-            match authorization.status() {
+            // Retrieve authorizations for the order, choosing a challenge for each domain.
+            let authorizations = order.authorize(|identifier| preferences(&failed, identifier))
+                .or_exit("Failed to retrieve order authorizations");
 
-                // Authorization pending, attempt to create the necessary TXT DNS record.
-                AuthStatus::Pending => {
+            // Remember which challenge type each domain is attempted with.
+            let attempted: Vec<(String, ChallengeType)> = authorizations.iter()
+                .filter_map(|authorization| {
                     let challenge = &authorization.challenge;
-                    let split_request_index = requested_domains.iter()
-                        .position(|(domain, _)| domain == &challenge.domain);
+                    ChallengeType::try_from(challenge.r#type.as_str()).ok()
+                        .map(|ct| (challenge.domain.clone(), ct))
+                })
+                .collect();
+
+            // Set up the necessary challenge responses.
+            let mut responder = Responder::new(dns_api.as_ref(), config.http_port.unwrap_or(80),
+                config.tls_port.unwrap_or(443));
+            for authorization in authorizations.iter() {
+                match authorization.status() {
+
+                    // Authorization pending, attempt to set up the challenge response (e.g. a TXT DNS record).
+                    AuthStatus::Pending => {
+                        let challenge = &authorization.challenge;
+                        let requested = requested_domains.iter()
+                            .find(|domain| domain.name == challenge.domain);
+                        let root = requested.map_or(challenge.domain.as_str(), |domain| domain.root);
+                        let webroot = requested.and_then(|domain| domain.webroot);
+
+                        if let Err(e) = responder.deploy(challenge, root, webroot) {
+                            eprintln!("{e}");
+                            responder.cleanup()
+                                .or_exit("Failed to revert challenge responses");
+                            std::process::exit(1);
+                        }
+                    },
+
+                    AuthStatus::Invalid => {
+                        eprintln!("Authorization for {} became invalid, exiting...", authorization.challenge.domain);
+                        responder.cleanup()
+                            .or_exit("Failed to revert challenge responses");
+                        std::process::exit(2);
+                    },
+
+                    AuthStatus::Valid => continue,
+                }
+            }
 
-                    if let Some(index) = split_request_index {
-                        let (root, sub) = &cert_map[cert_index][index];
+            // Wait a little bit for DNS records to propagate.
+            if responder.created_dns_records() {
+                std::thread::sleep(Duration::from_secs(20));
+            }
 
-                        dns_api.create(Some(sub), root, &challenge.response)
-                            .expect(&format!("Failed to create DNS TXT record for {}", root));
+            // Notify that the challenge responses are ready to be checked, and wait for the order to
+            // complete. Returns whether to retry with the next challenge types. Errors are only
+            // reported once the challenge responses are reverted.
+            let ready = order.ready(authorizations);
+            let complete_order = || -> Result<bool, String> {
+                ready.map_err(|e| format!("Failed to notify of challenge readiness: {e}"))?;
+
+                // Loop while waiting for order completion.
+                let mut poller = config.poller();
+                loop {
+                    let status = order.status()
+                        .map_err(|e| format!("Failed to check the order status: {e}"))?;
+
+                    match status {
+                        OrderStatus::Pending | OrderStatus::Processing => {},
+
+                        OrderStatus::Ready => {
+                            order.finalize(&key, &csr_options)
+                                .map_err(|e| format!("Failed to finalize order: {e}"))?;
+                        },
+
+                        // Order became invalid, revert the previously set up challenge responses, and
+                        // retry with the next challenge type of each domain that failed, if any.
+                        OrderStatus::Invalid => {
+                            match order.error() {
+                                Some(problem) => eprintln!("Order became invalid: {problem}"),
+                                None => eprintln!("Order became invalid."),
+                            }
+
+                            let problems = order.challenge_errors()
+                                .unwrap_or_else(|e| {
+                                    eprintln!("Failed to retrieve the failed challenges: {e}");
+                                    Vec::new()
+                                });
+                            for problem in &problems {
+                                eprintln!("  - {problem}");
+                            }
+
+                            let mut fell_back = false;
+                            for problem in &problems {
+                                let Some(ref identifier) = problem.identifier else { continue };
+
+                                if let Some((domain, ct)) = attempted.iter().find(|(domain, _)| *domain == identifier.value) {
+                                    if !failed.contains(&(domain.clone(), *ct)) {
+                                        failed.push((domain.clone(), *ct));
+                                        fell_back = true;
+                                    }
+                                }
+                            }
+
+                            let exhausted: Vec<&str> = domains.iter()
+                                .filter(|domain| failed.iter().any(|(name, _)| name == *domain))
+                                .filter(|domain| preferences(&failed, domain).is_empty())
+                                .copied()
+                                .collect();
+                            for domain in &exhausted {
+                                eprintln!("Every challenge type configured for {domain} failed.");
+                            }
+                            let retry = fell_back && exhausted.is_empty();
+
+                            if retry {
+                                eprintln!("Reverting challenge responses and retrying with the next challenge types...");
+                            } else {
+                                eprintln!("Reverting challenge responses and exiting...");
+                            }
+
+                            return Ok(retry);
+                        },
+
+                        OrderStatus::Valid => {
+                            let (cert, key) = order.download(config.preferred_chain.as_deref())
+                                .map_err(|e| format!("Failed to download the certificate: {e}"))?;
+                            for e in order.chain_errors() {
+                                eprintln!("Failed to retrieve an alternate certificate chain: {e}");
+                            }
+
+                            println!("Order for '{cert_name}' complete! Writing files...");
+
+                            // Attempt to write the certificate and private key files.
+                            store.save(cert_name, &output_options, &cert, key.as_deref())
+                                .map_err(|e| format!("Failed to write certificate files: {e}"))?;
+
+                            println!("Done!");
+
+                            return Ok(false);
+                        }
                     }
 
-                },
+                    // Wait before checking again, giving up if the order is taking too long to complete.
+                    if !poller.wait(order.retry_after()) {
+                        eprintln!("Order took too long to complete.");
+                        eprintln!("Reverting challenge responses and exiting...");
 
-                AuthStatus::Invalid => {
-                    eprintln!("Authorization for {} became invalid, exiting...", authorization.challenge.domain);
-                    std::process::exit(2);
-                },
+                        return Ok(false);
+                    }
+                }
+            };
+            let outcome = complete_order();
 
-                AuthStatus::Valid => continue,
+            if let Err(ref e) = outcome {
+                eprintln!("{e}");
+                eprintln!("Reverting challenge responses and exiting...");
+            }
+
+            responder.cleanup()
+                .or_exit("Failed to revert challenge responses");
+
+            match outcome {
+                Ok(true) => continue,
+                Ok(false) => break,
+                Err(_) => std::process::exit(1),
             }
         }
+    }
+}
 
-        // Wait a little bit for DNS records to propagate.
-        std::thread::sleep(Duration::from_secs(20));
-
-        // Notify that TXT DNS records are ready to be checked.
-        order.ready(authorizations)
-            .expect("Failed to notify of DNS records readiness");
-
-        // Loop while waiting for order completion.
-        let start_time = Instant::now();
-        let mut wait_time = Duration::from_secs(5);
-        while let Ok(status) = order.status() {
-            match status {
-                OrderStatus::Pending | OrderStatus::Processing => {
-                    // Wait a little longer next time.
-                    if wait_time.as_secs() < 60 {
-                        wait_time += Duration::from_secs(5);
-                    }
-                },
+/// Revoke the certificate written to the output directory as `name`. The request is signed by the
+/// given account, or otherwise by the certificate's own private key.
+fn revoke_certificate(config: &Config, authority: &CertificateAuthority, account: Option<&Account>, args: &[String]) -> Result<(), String> {
+    let name = args.first()
+        .ok_or("Must specify the name of the certificate to revoke!")?;
+    let reason = args.get(1)
+        .map(|r| RevocationReason::try_from(r.as_str()))
+        .transpose()
+        .map_err(|e| e.to_string())?;
+
+    let store = CertificateStore::new(PathBuf::from(&config.output_directory), config.archive);
+    let options = config.output_options(name)?;
+    let cert_der = store.load_certificate(name, &options)?
+        .ok_or(format!("No certificate named '{name}' was found in {}", config.output_directory))?;
+
+    match account {
+        Some(account) => account.revoke(&cert_der, reason),
+        None => {
+            let key_der = store.load_key(name, &options)?
+                .ok_or(format!("No private key for '{name}' was found in {}", config.output_directory))?;
+
+            revoke_with_certificate_key(authority, &cert_der, &key_der, reason)
+        },
+    }.map_err(|e| e.to_string())
+}
 
-                OrderStatus::Ready => {
-                    order.finalize()
-                        .expect("Failed to finalize order");
-                },
+/// Make the archived version before the one in use live again, e.g. after a renewal broke a service.
+fn rollback_certificate(config: &Config, args: &[String]) -> Result<u32, String> {
+    let name = args.first()
+        .ok_or("Must specify the name of the certificate to roll back!")?;
 
-                // Order became invalid, just delete the previously created DNS records.
-                OrderStatus::Invalid => {
-                    eprintln!("Order became invalid. Reverting created TXT DNS records and exiting...");
+    CertificateStore::new(PathBuf::from(&config.output_directory), config.archive)
+        .rollback(name)
+}
 
-                    for (root, sub) in &cert_map[cert_index] {
-                        dns_api.delete(Some(sub), root)
-                            .expect(&format!("Failed to delete DNS TXT record for {}", root));
-                    }
+fn main() {
+    let config: Config = get_config()
+        .or_exit("Failed to load configuration file");
 
-                    break;
-                },
+    // Load the stored account, or generate (and store) one.
+    let authority = config.certificate_authority()
+        .or_exit("Failed to select a certificate authority");
 
-                OrderStatus::Valid => {
-                    let output_dir = PathBuf::from(&config.output_directory);
-                    let (cert, key) = order.download()
-                        .expect("Failed to download the certificate");
+    // Trust any additional root certificates, e.g. those of an internal Certificate Authority.
+    if let Some(ref path) = config.ca_certificates {
+        let roots = std::fs::read_to_string(path)
+            .map_err(|e| format!("{path}: {e}"))
+            .and_then(|pem| model::decode_pem(&pem, "CERTIFICATE").map_err(|e| e.to_string()))
+            .or_exit("Failed to load additional root certificates");
 
-                    println!("Order for '{cert_name}' complete! Writing files...");
+        model::trust_root_certificates(roots)
+            .or_exit("Failed to trust additional root certificates");
+    }
+    let args: Vec<String> = std::env::args().skip(2).collect();
 
-                    // Attempt to create the output directory.
-                    std::fs::create_dir_all(&output_dir)
-                        .expect("Failed to create output directory");
+    // Revoking with the certificate's own key must work without an account, e.g. after losing its key.
+    if let Some("revoke-with-key") = args.first().map(String::as_str) {
+        revoke_certificate(&config, &authority, None, &args[1..])
+            .or_exit("Failed to revoke certificate");
 
-                    // Attempt to write the certificate and private key files.
-                    std::fs::write(output_dir.join(format!("{cert_name}.pem")), cert.as_bytes())
-                        .expect("Failed to write PEM encoded certificate file");
-                    std::fs::write(output_dir.join(format!("{cert_name}.der")), &key)
-                        .expect("Failed to write DER encoded private key file");
+        println!("Certificate revoked.");
+        return;
+    }
 
-                    for (root, sub) in &cert_map[cert_index] {
-                        dns_api.delete(Some(sub), root)
-                            .expect(&format!("Failed to delete DNS TXT record for {}", root));
-                    }
+    // Rolling back only touches the output directory, so no account is needed either.
+    if let Some("rollback") = args.first().map(String::as_str) {
+        let version = rollback_certificate(&config, &args[1..])
+            .or_exit("Failed to roll back certificate");
 
-                    println!("Done!");
-                    
-                    break;
-                }
-            }
+        println!("Certificate '{}' rolled back to version {version}.", args[1]);
+        return;
+    }
 
-            // Exit if waiting for order completion took more than 5 minutes.
-            if start_time.elapsed().as_secs() > 300 {
-                eprintln!("Order took longer than 5 minutes to complete.");
-                eprintln!("Reverting created TXT DNS records and exiting...");
+    let account_store = AccountStore::new(config.account_directory.as_ref()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(&config.output_directory).join("accounts")));
 
-                for (root, sub) in &cert_map[cert_index] {
-                    dns_api.delete(Some(sub), root)
-                        .expect(&format!("Failed to delete DNS TXT record for {}", root));
-                }
+    // Only requesting certificates registers a new account, maintenance commands need an existing one.
+    let mut account = match args.first().map(String::as_str) {
+        None => account_store.load_or_generate(&authority, &config.contacts(), config.terms_of_service.as_deref(),
+            config.external_account_binding.as_ref())
+            .or_exit("Failed to generate/load account"),
 
-                break;
-            }
-        }
+        Some("rollover" | "deactivate" | "revoke") => account_store.load(&authority)
+            .and_then(|account| account.ok_or(format!("There is no stored account for {}", authority.url())))
+            .or_exit("Failed to load account"),
+
+        Some(command) => {
+            eprintln!("Unknown command '{command}'!");
+            std::process::exit(1);
+        },
+    };
+
+    match args.first().map(String::as_str) {
+        None => request_certificates(&config, &mut account),
+
+        // Replace the account key, keeping the account itself.
+        Some("rollover") => {
+            account.change_key()
+                .or_exit("Failed to roll over the account key");
+
+            println!("Account key replaced.");
+        },
+
+        // Deactivate the account, e.g. when decommissioning a host or if its key may have leaked.
+        Some("deactivate") => {
+            account.deactivate()
+                .or_exit("Failed to deactivate the account");
+
+            println!("Account deactivated.");
+        },
+
+        Some("revoke") => {
+            revoke_certificate(&config, &authority, Some(&account), &args[1..])
+                .or_exit("Failed to revoke certificate");
+
+            println!("Certificate revoked.");
+        },
+
+        Some(command) => unreachable!("unknown command '{command}'"),
     }
-}
\ No newline at end of file
+
+    // Store the account again, keeping track of any changes made during this run.
+    account_store.save(&authority, &account)
+        .or_exit("Failed to store account");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn requests(toml: &str) -> Vec<CertificateRequest> {
+        let config: Config = toml::from_str(&format!("directory = \"out\"\n{toml}")).unwrap();
+        config.certs
+    }
+
+    fn names<'a>(converted: &'a [(&str, Vec<RequestedDomain>)]) -> Vec<&'a str> {
+        converted[0].1.iter()
+            .map(|domain| domain.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn derives_hosts_from_root() {
+        let requests = requests(r#"
+            [[certificate]]
+            name = "cert"
+            [[certificate.domain]]
+            root = "example.com"
+            hosts = [ ".", "www", "*", "*.api" ]
+            [[certificate.domain]]
+            root = "example.org"
+        "#);
+        let converted = convert_requests(&requests);
+
+        assert_eq!(converted[0].0, "cert");
+        assert_eq!(names(&converted), ["example.com", "www.example.com", "*.example.com", "*.api.example.com", "example.org"]);
+        assert!(converted[0].1.iter().take(4).all(|domain| domain.root == "example.com"));
+    }
+
+    #[test]
+    fn ip_roots_ignore_hosts_and_default_to_http_and_tls_alpn() {
+        let requests = requests(r#"
+            [[certificate]]
+            name = "ip"
+            [[certificate.domain]]
+            root = "2001:DB8:0::1"
+            hosts = [ "www" ]
+            [[certificate.domain]]
+            root = "example.com"
+        "#);
+        let converted = convert_requests(&requests);
+
+        // IPv6 addresses are written the way the server reports them.
+        assert_eq!(names(&converted), ["2001:db8::1", "example.com"]);
+        assert_eq!(converted[0].1[0].challenges, DEFAULT_IP_CHALLENGES);
+        assert_eq!(converted[0].1[1].challenges, DEFAULT_CHALLENGES);
+    }
+
+    #[test]
+    fn domain_settings_override_certificate_settings() {
+        let requests = requests(r#"
+            [[certificate]]
+            name = "cert"
+            challenge = [ "http-01", "tls-alpn-01" ]
+            webroot = "/var/www"
+            [[certificate.domain]]
+            root = "example.com"
+            [[certificate.domain]]
+            root = "example.org"
+            challenge = "dns-01"
+            webroot = "/srv/www"
+        "#);
+        let converted = convert_requests(&requests);
+        let [com, org] = converted[0].1.as_slice() else { panic!("expected two domains") };
+
+        assert_eq!(com.challenges, [ChallengeType::Http, ChallengeType::TlsAlpn]);
+        assert_eq!(com.webroot, Some(Path::new("/var/www")));
+        assert_eq!(org.challenges, [ChallengeType::DNS]);
+        assert_eq!(org.webroot, Some(Path::new("/srv/www")));
+    }
+}
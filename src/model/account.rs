@@ -1,11 +1,16 @@
 use super::*;
 use super::signed_json::*;
 use super::order::*;
+use super::revocation::*;
 
 use std::cell::Cell;
+use hmac::{Hmac, Mac};
 use rand::rngs::OsRng;
-use p256::ecdsa::{Signature, SigningKey};
-use p256::ecdsa::signature::Signer;
+use p256::ecdsa::SigningKey;
+use p256::ecdsa::signature::{SignatureEncoding, Signer};
+use p256::pkcs8::DecodePrivateKey;
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use rsa::traits::PublicKeyParts;
 
 #[derive(Deserialize)]
 pub(crate) struct AccountData {
@@ -14,9 +19,107 @@ pub(crate) struct AccountData {
     orders: Option<String>,
 }
 
+/// A private key to sign requests with. Account keys are always P-256, but a certificate's own key
+/// (used to revoke it) can be any [KeyType].
+/// ([RFC 8555§6.2](https://www.rfc-editor.org/rfc/rfc8555.html#section-6.2))
+enum JwsSigningKey {
+    Es256(SigningKey),
+    Es384(p384::ecdsa::SigningKey),
+    Rs256(Box<rsa::pkcs1v15::SigningKey<Sha256>>),
+    EdDsa(Ed25519KeyPair),
+}
+
+impl JwsSigningKey {
+    /// Use a PKCS#8 DER encoded private key of any supported type.
+    fn from_pkcs8_der(bytes: &[u8]) -> Result<Self, Error> {
+        if let Ok(key) = SigningKey::from_pkcs8_der(bytes) {
+            Ok(Self::Es256(key))
+        } else if let Ok(key) = p384::ecdsa::SigningKey::from_pkcs8_der(bytes) {
+            Ok(Self::Es384(key))
+        } else if let Ok(key) = rsa::RsaPrivateKey::from_pkcs8_der(bytes) {
+            Ok(Self::Rs256(Box::new(rsa::pkcs1v15::SigningKey::new(key))))
+        // The pkcs8 crate rejects how ring (and thus rcgen) encodes Ed25519 public keys.
+        } else if let Ok(key) = Ed25519KeyPair::from_pkcs8_maybe_unchecked(bytes) {
+            Ok(Self::EdDsa(key))
+        } else {
+            Err(Error::SigningKeyFromBytes("not a P-256, P-384, RSA or Ed25519 PKCS#8 key".to_string()))
+        }
+    }
+
+    /// The JWS algorithm of signatures made with this key.
+    fn alg(&self) -> &'static str {
+        match self {
+            Self::Es256(_) => "ES256",
+            Self::Es384(_) => "ES384",
+            Self::Rs256(_) => "RS256",
+            Self::EdDsa(_) => "EdDSA",
+        }
+    }
+
+    /// The public key as a [Jwk].
+    fn jwk(&self) -> Jwk {
+        let encode = Base64UrlUnpadded::encode_string;
+        match self {
+            Self::Es256(key) => {
+                let point = key.verifying_key().to_encoded_point(false);
+                Jwk::Ec {
+                    alg: self.alg(),
+                    crv: "P-256",
+                    kty: "EC",
+                    usage: "sig",
+                    x: encode(point.x().unwrap()),
+                    y: encode(point.y().unwrap()),
+                }
+            },
+
+            Self::Es384(key) => {
+                let point = key.verifying_key().to_encoded_point(false);
+                Jwk::Ec {
+                    alg: self.alg(),
+                    crv: "P-384",
+                    kty: "EC",
+                    usage: "sig",
+                    x: encode(point.x().unwrap()),
+                    y: encode(point.y().unwrap()),
+                }
+            },
+
+            Self::Rs256(key) => {
+                let key: &rsa::RsaPrivateKey = key.as_ref().as_ref();
+                Jwk::Rsa {
+                    alg: self.alg(),
+                    e: encode(&key.e().to_bytes_be()),
+                    kty: "RSA",
+                    n: encode(&key.n().to_bytes_be()),
+                    usage: "sig",
+                }
+            },
+
+            Self::EdDsa(key) => Jwk::Okp {
+                alg: self.alg(),
+                crv: "Ed25519",
+                kty: "OKP",
+                usage: "sig",
+                x: encode(key.public_key().as_ref()),
+            },
+        }
+    }
+
+    /// Sign the message, giving the signature as JWS expects it (e.g. ECDSA's r and s concatenated).
+    fn sign(&self, message: &[u8]) -> Vec<u8> {
+        match self {
+            Self::Es256(key) => Signer::<p256::ecdsa::Signature>::sign(key, message).to_vec(),
+            Self::Es384(key) => Signer::<p384::ecdsa::Signature>::sign(key, message).to_vec(),
+            Self::Rs256(key) => key.sign(message).to_vec(),
+            Self::EdDsa(key) => key.sign(message).as_ref().to_vec(),
+        }
+    }
+}
+
 /// Contains information necessary for signing POST-as-GET requests.
 pub(crate) struct Crypto {
-    signing_key: SigningKey,
+    signing_key: JwsSigningKey,
+    jwk: Jwk,
     header_key: SignedJsonHeaderKey,
     pub(crate) thumbprint: String,
 }
@@ -34,31 +137,21 @@ impl TryFrom<SigningKey> for Crypto {
     type Error = Error;
 
     fn try_from(signing_key: SigningKey) -> Result<Self, Self::Error> {
-        let verifying_key = signing_key.verifying_key();
-
-        let point = verifying_key.to_encoded_point(false);
-        let encoded_x = Base64UrlUnpadded::encode_string(point.x().unwrap());
-        let encoded_y = Base64UrlUnpadded::encode_string(point.y().unwrap());
-        let header_key = SignedJsonHeaderKey::Jwk {
-            alg: "ES256",
-            crv: "P-256",
-            kty: "EC",
-            usage: "sig",
-            x: encoded_x.clone(),
-            y: encoded_y.clone(),
-        };
+        Self::try_from(JwsSigningKey::Es256(signing_key))
+    }
+}
 
-        let thumbprint_data = SignedJsonThumbprint {
-            crv: "P-256",
-            kty: "EC",
-            x: encoded_x,
-            y: encoded_y,
-        };
-        
-        let thumbprint_hash = Sha256::digest(to_json_vec(&thumbprint_data)?);
+impl TryFrom<JwsSigningKey> for Crypto {
+    type Error = Error;
+
+    fn try_from(signing_key: JwsSigningKey) -> Result<Self, Self::Error> {
+        let jwk = signing_key.jwk();
+        let header_key = SignedJsonHeaderKey::Jwk(jwk.clone());
+
+        let thumbprint_hash = Sha256::digest(to_json_vec(&jwk.thumbprint_data())?);
         let thumbprint = Base64UrlUnpadded::encode_string(&thumbprint_hash);
 
-        Ok(Self { signing_key, header_key, thumbprint })
+        Ok(Self { signing_key, jwk, header_key, thumbprint })
     }
 }
 
@@ -68,17 +161,45 @@ impl Crypto {
         Crypto::try_from(SigningKey::random(&mut OsRng::default()))
     }
 
+    /// Use a PKCS#8 DER encoded private key, e.g. one written alongside a certificate.
+    pub(crate) fn from_pkcs8_der(bytes: &[u8]) -> Result<Self, Error> {
+        Self::try_from(JwsSigningKey::from_pkcs8_der(bytes)?)
+    }
+
+    /// Sign the [Payload] and send an HTTP POST. If the server rejects the nonce (which happens
+    /// routinely behind load balancers), retry with the fresh nonce sent along with the error.
+    /// ([RFC 8555§6.5](https://www.rfc-editor.org/rfc/rfc8555.html#section-6.5))
+    pub(crate) fn post(&self, url: &str, mut nonce: String, new_nonce_url: &str, payload: Payload) -> Result<Response, Error> {
+        let mut retries = 0;
+        loop {
+            let signed_json = self.sign(url, Some(&nonce), payload.clone())?;
+
+            match http_post(url, signed_json) {
+                Err(Error::HttpPost(problem)) if problem.is("badNonce") && retries < NONCE_RETRIES => {
+                    retries += 1;
+                    std::thread::sleep(nonce_backoff(retries, problem.retry_after));
+                    nonce = match problem.nonce {
+                        Some(nonce) => nonce,
+                        None => new_nonce(new_nonce_url)?,
+                    };
+                },
+
+                result => return result,
+            }
+        }
+    }
+
     /// Used to update a JWT header key value.
     fn set_header_key(&mut self, key: SignedJsonHeaderKey) {
         self.header_key = key;
     }
 
-    /// Sign the given [Payload].
-    fn sign(&self, url: &str, nonce: &str, payload: Payload) -> Result<SignedJson, Error> {
+    /// Sign the given [Payload]. The nonce is only left out for JWS objects nested in another.
+    pub(crate) fn sign(&self, url: &str, nonce: Option<&str>, payload: Payload) -> Result<SignedJson, Error> {
         let header = SignedJsonHeader {
-            alg: "ES256",
+            alg: self.signing_key.alg(),
             key: &self.header_key,
-            nonce: nonce.to_string(),
+            nonce: nonce.map(|n| n.to_string()),
             url: url.to_string(),
         };
 
@@ -88,13 +209,41 @@ impl Crypto {
             Payload::EmptyObject {} => Base64UrlUnpadded::encode_string(&to_json_vec(&payload)?),
             _ => Base64UrlUnpadded::encode_string(&to_json_vec(&payload)?)
         };
-        let signature: Signature = self.signing_key
+        let signature = self.signing_key
             .sign(format!("{}.{}", encoded_header, payload).as_bytes());
 
         Ok(SignedJson {
             protected: encoded_header,
             payload: payload,
-            signature: Base64UrlUnpadded::encode_string(signature.to_vec().as_ref()),
+            signature: Base64UrlUnpadded::encode_string(&signature),
+        })
+    }
+}
+
+impl ExternalAccountBinding {
+    /// Sign the account's [Jwk] with the HMAC key, producing the JWS for a new account request.
+    fn sign(&self, url: &str, jwk: &Jwk) -> Result<SignedJson, Error> {
+        let hmac_key = Base64UrlUnpadded::decode_vec(self.hmac_key.trim_end_matches('='))
+            .map_err(|e| Error::InvalidHmacKey(e.to_string()))?;
+        let header_key = SignedJsonHeaderKey::Kid(self.kid.clone());
+        let header = SignedJsonHeader {
+            alg: "HS256",
+            key: &header_key,
+            nonce: None,
+            url: url.to_string(),
+        };
+
+        let encoded_header = Base64UrlUnpadded::encode_string(&to_json_vec(&header)?);
+        let payload = Base64UrlUnpadded::encode_string(&to_json_vec(&Payload::Jwk(jwk))?);
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(&hmac_key)
+            .map_err(|e| Error::InvalidHmacKey(e.to_string()))?;
+        mac.update(format!("{}.{}", encoded_header, payload).as_bytes());
+
+        Ok(SignedJson {
+            protected: encoded_header,
+            payload: payload,
+            signature: Base64UrlUnpadded::encode_string(&mac.finalize().into_bytes()),
         })
     }
 }
@@ -118,6 +267,7 @@ impl TryFrom<&[u8]> for Account {
         let mut crypto: Option<Crypto> = None;
         let mut directory: Option<Directory> = None;
         let mut order_urls: Option<Vec<String>> = None;
+        let mut status: Option<String> = None;
         while index < bytes.len() {
             index += match bytes[index] {
                 // `Crypto`
@@ -168,6 +318,22 @@ impl TryFrom<&[u8]> for Account {
                     urls_len
                 },
 
+                // Account status
+                3 => {
+                    index += 1;
+
+                    let len_bytes: [u8; USIZE_LEN] = bytes[index..index+USIZE_LEN].try_into()
+                        .map_err(|_| Error::ParseFromBytes("status bytes length".to_string()))?;
+                    
+                    let status_len = usize::from_be_bytes(len_bytes);
+                    index += USIZE_LEN;
+
+                    let status_bytes = &bytes[index..index+status_len];
+                    status = Some(from_json_bytes(status_bytes)?);
+                    
+                    status_len
+                },
+
                 // Unknown
                 _ => return Err(Error::ParseFromBytes("unknown bytes".to_string())),
             };
@@ -177,26 +343,33 @@ impl TryFrom<&[u8]> for Account {
         let mut crypto = crypto.unwrap();
         let directory = directory.unwrap();
 
+        // Don't bother the server with an account known to be deactivated.
+        if let Some(status) = status.filter(|s| s != "valid") {
+            return Err(Error::AccountStatus(status));
+        }
+
         if let Some(ref kid) = directory.account {
             crypto.set_header_key(SignedJsonHeaderKey::Kid(kid.clone()));
         }
 
-        let response = http_head(&directory.new_nonce)?;
-        let nonce = response.header("replay-nonce")
-            .expect("failed to retrieve nonce");
-
+        let nonce = new_nonce(&directory.new_nonce)?;
         let account_url = directory.account.as_ref().unwrap();
-        let signed_json = crypto.sign(account_url, nonce, Payload::Empty)?;
-        let response = http_post(account_url, signed_json)?;
+        let response = crypto.post(account_url, nonce, &directory.new_nonce, Payload::Empty)?;
         let new_nonce = response.header("replay-nonce")
             .map(|s| s.to_string());
 
+        let data: AccountData = response.into_json()
+            .map_err(|e| Error::ResponseIntoJson(e.to_string()))?;
+
+        if data.status != "valid" {
+            return Err(Error::AccountStatus(data.status));
+        }
+
         Ok(Self {
             directory: directory,
             crypto: crypto,
             nonce: Cell::new(new_nonce),
-            data: response.into_json()
-                .map_err(|e| Error::ResponseIntoJson(e.to_string()))?,
+            data: data,
             order_urls: order_urls.unwrap_or(Vec::new()),
         })
 
@@ -204,21 +377,40 @@ impl TryFrom<&[u8]> for Account {
 }
 
 impl Account {
-    /// Generate a new account for the chosen Certificate Authority.
-    pub fn generate(ca: CertificateAuthority) -> Result<Self, Error> {
-        let mut directory: Directory = get_as_json(ca.into())?;
+    /// Generate a new account for the chosen Certificate Authority, with the given contact URLs.
+    /// If the Certificate Authority has terms of service, `terms_of_service` must be the same URL.
+    /// ([RFC 8555§7.3](https://www.rfc-editor.org/rfc/rfc8555.html#section-7.3))
+    /// Accounts with Certificate Authorities requiring it are bound to an external account through `eab`.
+    pub fn generate(ca: &CertificateAuthority, contact: &[String], terms_of_service: Option<&str>, eab: Option<&ExternalAccountBinding>) -> Result<Self, Error> {
+        let mut directory: Directory = get_as_json(ca.url())?;
+
+        // Refuse to create an account unless the current terms of service were agreed to.
+        let current_terms = directory.meta.as_ref()
+            .and_then(|meta| meta.terms_of_service.as_deref());
+        if let Some(terms_url) = current_terms {
+            if terms_of_service != Some(terms_url) {
+                return Err(Error::TermsOfServiceNotAgreed(terms_url.to_string()));
+            }
+        }
+
+        let external_account_required = directory.meta.as_ref()
+            .and_then(|meta| meta.external_account_required)
+            .unwrap_or(false);
+        if external_account_required && eab.is_none() {
+            return Err(Error::ExternalAccountRequired);
+        }
+
         let mut crypto = Crypto::generate()?;
-        let nonce_response = http_head(&directory.new_nonce)?;
-        let nonce = nonce_response.header("replay-nonce")
-            .expect("failed to retrieve nonce");
-        
+        let nonce = new_nonce(&directory.new_nonce)?;
         let payload = Payload::NewAccount {
-            contact: &[],
-            terms_of_service_agreed: true,
+            contact,
+            terms_of_service_agreed: current_terms.is_some(),
+            external_account_binding: eab
+                .map(|eab| eab.sign(&directory.new_account, &crypto.jwk))
+                .transpose()?,
         };
 
-        let signed_json = crypto.sign(&directory.new_account, nonce, payload)?;
-        let response = http_post(&directory.new_account, signed_json)?;
+        let response = crypto.post(&directory.new_account, nonce, &directory.new_nonce, payload)?;
 
         // Extract the new nonce.
         let new_nonce = response.header("replay-nonce")
@@ -243,19 +435,97 @@ impl Account {
         })
     }
 
+    /// The contact URLs currently registered with the account.
+    pub fn contacts(&self) -> &[String] {
+        self.data.contact.as_deref().unwrap_or(&[])
+    }
+
+    /// Replace the contact URLs registered with the account.
+    /// ([RFC 8555§7.3.2](https://www.rfc-editor.org/rfc/rfc8555.html#section-7.3.2))
+    pub fn update_contacts(&mut self, contact: &[String]) -> Result<(), Error> {
+        let account_url = self.directory.account.clone()
+            .ok_or(Error::AccountUrlUnavailable)?;
+
+        self.data = self.post_as_json(&account_url, Payload::UpdateAccount { contact })?;
+
+        Ok(())
+    }
+
+    /// Replace the account key with a newly generated one, keeping the account (and its orders).
+    /// The new key signs an inner JWS naming the account and old key, which the old key then signs.
+    /// ([RFC 8555§7.3.5](https://www.rfc-editor.org/rfc/rfc8555.html#section-7.3.5))
+    pub fn change_key(&mut self) -> Result<(), Error> {
+        let key_change_url = self.directory.key_change.clone()
+            .ok_or(Error::KeyChangeUnavailable)?;
+        let account_url = self.directory.account.clone()
+            .ok_or(Error::AccountUrlUnavailable)?;
+
+        let mut new_crypto = Crypto::generate()?;
+        let inner_json = new_crypto.sign(&key_change_url, None, Payload::KeyChange {
+            account: &account_url,
+            old_key: &self.crypto.jwk,
+        })?;
+
+        self.post(&key_change_url, Payload::Nested(&inner_json))?;
+
+        new_crypto.set_header_key(SignedJsonHeaderKey::Kid(account_url));
+        self.crypto = new_crypto;
+
+        Ok(())
+    }
+
+    /// Deactivate the account. The server refuses any further requests made with it.
+    /// ([RFC 8555§7.3.6](https://www.rfc-editor.org/rfc/rfc8555.html#section-7.3.6))
+    pub fn deactivate(&mut self) -> Result<(), Error> {
+        let account_url = self.directory.account.clone()
+            .ok_or(Error::AccountUrlUnavailable)?;
+
+        self.data = self.post_as_json(&account_url, Payload::UpdateStatus { status: "deactivated" })?;
+
+        Ok(())
+    }
+
+    /// Revoke a (DER encoded) certificate issued to this account, optionally giving a reason.
+    /// ([RFC 8555§7.6](https://www.rfc-editor.org/rfc/rfc8555.html#section-7.6))
+    pub fn revoke(&self, cert_der: &[u8], reason: Option<RevocationReason>) -> Result<(), Error> {
+        let payload = Payload::RevokeCert {
+            certificate: Base64UrlUnpadded::encode_string(cert_der),
+            reason: reason.map(|r| r as u8),
+        };
+
+        self.post(&self.directory.revoke_cert, payload)?;
+
+        Ok(())
+    }
+
+    /// Fetch the Certificate Authority's directory again, keeping the stored account URL.
+    /// Keeps endpoints and metadata current for accounts stored before they were known.
+    pub fn refresh_directory(&mut self, ca: &CertificateAuthority) -> Result<(), Error> {
+        let mut directory: Directory = get_as_json(ca.url())?;
+        directory.account = self.directory.account.take();
+        self.directory = directory;
+
+        Ok(())
+    }
+
+    /// Fetch the Certificate Authority's renewal suggestion for the certificate with the given ID
+    /// (see [renewal::IssuedCertificate]), returning `None` if it doesn't support ACME Renewal Information.
+    pub fn renewal_info(&self, cert_id: &str) -> Result<Option<renewal::RenewalInfo>, Error> {
+        let Some(ref renewal_info_url) = self.directory.renewal_info else {
+            return Ok(None);
+        };
+
+        let url = format!("{}/{cert_id}", renewal_info_url.trim_end_matches('/'));
+        let response = http_get(&url)?;
+
+        renewal::RenewalInfo::from_response(response, cert_id).map(Some)
+    }
+
     /// Gets the last stored nonce, or asks the server for a new one.
     fn get_nonce(&self) -> Result<String, Error> {
-        if let Some(nonce) = self.nonce.take() {
-            Ok(nonce)
-        } else {
-            let nonce = http_head(&self.directory.new_nonce)?
-                .header("replay-nonce")
-                .expect("failed to retrieve nonce")
-                .to_string();
-            
-            self.nonce.set(Some(nonce.clone()));
-
-            Ok(nonce)
+        match self.nonce.take() {
+            Some(nonce) => Ok(nonce),
+            None => new_nonce(&self.directory.new_nonce),
         }
     }
 
@@ -270,12 +540,23 @@ impl Account {
     /// Signs the [Payload], sends an HTTP POST, then updates the stored nonce.
     pub(crate) fn post(&self, url: &str, payload: Payload) -> Result<Response, Error> {
         let nonce = self.get_nonce()?;
-        let signed_json = self.crypto.sign(url, &nonce, payload)?;
-        let response = http_post(url, signed_json)?;
 
-        self.set_nonce(&response);
+        match self.crypto.post(url, nonce, &self.directory.new_nonce, payload) {
+            Ok(response) => {
+                self.set_nonce(&response);
+
+                Ok(response)
+            },
+
+            // Error responses carry a fresh nonce too.
+            Err(Error::HttpPost(problem)) => {
+                self.nonce.set(problem.nonce.clone());
 
-        Ok(response)
+                Err(Error::HttpPost(problem))
+            },
+
+            Err(e) => Err(e),
+        }
     }
 
     /// Same as `Account::post`, but additionally converts the response to a struct.
@@ -287,23 +568,54 @@ impl Account {
     }
 
     /// Request a new [Order] to be created for the given domains/identifiers.
-    /// ([RFC 8555§7.4](https://www.rfc-editor.org/rfc/rfc8555.html#section-7.4))
-    pub fn create_order(&mut self, domains: &[&str]) -> Result<Order, Error> {
+    /// ([RFC 8555§7.4](https://www.rfc-editor.org/rfc/rfc8555.html#section-7.4),
+    /// [RFC 9773§5](https://www.rfc-editor.org/rfc/rfc9773.html#section-5))
+    pub fn create_order(&mut self, domains: &[&str], options: &OrderOptions) -> Result<Order<'_>, Error> {
         let identifiers: Vec<Identifier> = domains.iter()
-            .map(|d| Identifier {
-                r#type: "dns".to_string(),
-                value: d.to_string(),
-            })
+            .map(|d| Identifier::new(d))
             .collect();
-        
-        let payload = Payload::NewOrder {
+
+        // Only profiles the Certificate Authority advertises can be selected.
+        if let Some(profile) = options.profile {
+            let profiles = self.directory.meta.as_ref()
+                .and_then(|meta| meta.profiles.as_ref());
+
+            if !profiles.is_some_and(|profiles| profiles.contains_key(profile)) {
+                let mut available: Vec<&str> = profiles.iter()
+                    .flat_map(|profiles| profiles.keys())
+                    .map(String::as_str)
+                    .collect();
+                available.sort();
+
+                return Err(Error::UnknownProfile(profile.to_string(), available.join(", ")));
+            }
+        }
+
+        let format_time = |time: Option<OffsetDateTime>| time
+            .map(|time| time.format(&Rfc3339))
+            .transpose()
+            .map_err(|e| Error::JsonToVec(e.to_string()));
+        let not_before = format_time(options.not_before)?;
+        let not_after = format_time(options.not_after)?;
+        let new_order = |replaces| Payload::NewOrder {
             identifiers: &identifiers,
+            replaces,
+            profile: options.profile,
+            not_before: not_before.clone(),
+            not_after: not_after.clone(),
         };
 
-        let response = self.post(&self.directory.new_order, payload)?;
+        let response = match self.post(&self.directory.new_order, new_order(options.replaces)) {
+            // The certificate was already replaced by another order, so just order a new one.
+            Err(Error::HttpPost(problem)) if options.replaces.is_some() && problem.is("alreadyReplaced") => {
+                self.post(&self.directory.new_order, new_order(None))?
+            },
+
+            result => result?,
+        };
         let order_url = match response.header("location") {
             Some(url) => url.to_string(),
-            None => return Err(Error::ResponseLacksLocation(response)),
+            None => return Err(Error::ResponseLacksLocation),
         };
         let order_data: OrderData = response.into_json()
             .map_err(|e| Error::ResponseIntoJson(e.to_string()))?;
@@ -315,6 +627,8 @@ impl Account {
             data: order_data,
             account: self,
             certificate: None,
+            retry_after: None,
+            chain_errors: Vec::new(),
         })
     }
 
@@ -326,7 +640,10 @@ impl Account {
         bytes.push(0);
 
         // Serialize the necessary `Crypto` information.
-        let crypto_bytes = self.crypto.signing_key.to_bytes();
+        let crypto_bytes = match &self.crypto.signing_key {
+            JwsSigningKey::Es256(signing_key) => signing_key.to_bytes(),
+            _ => unreachable!("account keys are always P-256"),
+        };
         bytes.extend_from_slice(&crypto_bytes.len().to_be_bytes());
         bytes.extend_from_slice(&crypto_bytes);
 
@@ -344,6 +661,64 @@ impl Account {
         bytes.extend_from_slice(&urls_bytes.len().to_be_bytes());
         bytes.extend_from_slice(&urls_bytes);
 
+        // Account status
+        bytes.push(3);
+
+        let status_bytes = to_json_vec(&self.data.status)?;
+        bytes.extend_from_slice(&status_bytes.len().to_be_bytes());
+        bytes.extend_from_slice(&status_bytes);
+
         Ok(bytes)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use p256::ecdsa::signature::Verifier;
+
+    #[test]
+    fn signs_with_every_certificate_key_type() {
+        let message = b"header.payload";
+        for (key_type, alg) in [
+            (KeyType::P256, "ES256"),
+            (KeyType::P384, "ES384"),
+            (KeyType::Rsa2048, "RS256"),
+            (KeyType::Ed25519, "EdDSA"),
+        ] {
+            let key_der = key_type.generate().unwrap().serialize_der();
+            let crypto = Crypto::from_pkcs8_der(&key_der).unwrap();
+            assert_eq!(crypto.signing_key.alg(), alg);
+
+            let signature = crypto.signing_key.sign(message);
+            let verified = match &crypto.signing_key {
+                JwsSigningKey::Es256(key) => key.verifying_key()
+                    .verify(message, &p256::ecdsa::Signature::from_slice(&signature).unwrap()).is_ok(),
+                JwsSigningKey::Es384(key) => key.verifying_key()
+                    .verify(message, &p384::ecdsa::Signature::from_slice(&signature).unwrap()).is_ok(),
+                JwsSigningKey::Rs256(key) => rsa::pkcs1v15::VerifyingKey::<Sha256>::new(key.as_ref().as_ref().to_public_key())
+                    .verify(message, &signature.as_slice().try_into().unwrap()).is_ok(),
+                JwsSigningKey::EdDsa(key) => ring::signature::UnparsedPublicKey::new(&ring::signature::ED25519, key.public_key())
+                    .verify(message, &signature).is_ok(),
+            };
+            assert!(verified, "{alg} signature doesn't verify");
+        }
+    }
+
+    #[test]
+    fn rejects_unsupported_keys() {
+        assert!(Crypto::from_pkcs8_der(b"not a key").is_err());
+    }
+
+    #[test]
+    fn thumbprints_use_only_required_members_in_order() {
+        let jwk = Jwk::Rsa { alg: "RS256", e: "AQAB".to_string(), kty: "RSA", n: "n".to_string(), usage: "sig" };
+        let json = String::from_utf8(to_json_vec(&jwk.thumbprint_data()).unwrap()).unwrap();
+        assert_eq!(json, r#"{"e":"AQAB","kty":"RSA","n":"n"}"#);
+
+        let jwk = Crypto::generate().unwrap().jwk;
+        let json = String::from_utf8(to_json_vec(&jwk.thumbprint_data()).unwrap()).unwrap();
+        assert!(json.starts_with(r#"{"crv":"P-256","kty":"EC","x":""#), "{json}");
+        assert!(!json.contains("alg") && !json.contains("use"), "{json}");
+    }
+}
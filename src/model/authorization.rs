@@ -19,12 +19,22 @@ impl From<&str> for AuthStatus {
 #[derive(Debug, Deserialize)]
 pub(crate) struct AuthData {
     pub status: String,
-    expires: Option<String>,
     pub identifier: Identifier,
     pub challenges: Vec<Challenge>,
     wildcard: Option<bool>,
 }
 
+impl AuthData {
+    /// The identifier as it was ordered, i.e. including the `*.` label of wildcard domains, which
+    /// the server strips from the authorization's identifier. ([RFC 8555§7.1.4](https://www.rfc-editor.org/rfc/rfc8555.html#section-7.1.4))
+    pub fn name(&self) -> String {
+        match self.wildcard {
+            Some(true) => format!("*.{}", self.identifier.value),
+            _ => self.identifier.value.clone(),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Authorization<'a> {
     pub(crate) url: &'a str,
@@ -0,0 +1,78 @@
+use super::*;
+
+use rcgen::KeyPair;
+use x509_cert::der::{Decode, Encode};
+use x509_cert::der::oid::AssociatedOid;
+use x509_cert::ext::pkix::SubjectAltName;
+use x509_cert::request::{CertReq, ExtensionReq};
+
+/// What a certificate is requested for.
+#[derive(Debug)]
+pub enum CertificateKey {
+    /// A new private key of the given type.
+    Generate(KeyType),
+    /// An existing PKCS#8 DER encoded private key, e.g. to keep key pins valid across renewals.
+    Existing(Vec<u8>),
+    /// An externally generated DER encoded Certificate Signing Request, submitted unchanged.
+    Csr(Vec<u8>),
+}
+
+impl CertificateKey {
+    /// Make sure an external Certificate Signing Request asks for exactly the given names.
+    pub fn check_names<S: AsRef<str>>(&self, names: &[S]) -> Result<(), Error> {
+        let Self::Csr(ref csr_der) = self else {
+            return Ok(());
+        };
+
+        let csr_names = csr_names(csr_der)?;
+        if !same_names(&csr_names, names) {
+            return Err(Error::CsrMismatch(csr_names.join(", ")));
+        }
+
+        Ok(())
+    }
+
+    /// The key pair (and its signature algorithm) to generate a Certificate Signing Request with.
+    pub(crate) fn key_pair(&self) -> Result<(KeyPair, &'static rcgen::SignatureAlgorithm), Error> {
+        match self {
+            Self::Generate(key_type) => Ok((key_type.generate()?, key_type.algorithm())),
+
+            Self::Existing(key_der) => {
+                let key_pair = KeyPair::from_der(key_der)
+                    .map_err(|e| Error::KeyLoad(e.to_string()))?;
+                let algorithm = [KeyType::P256, KeyType::P384, KeyType::Ed25519, KeyType::Rsa2048].iter()
+                    .map(|key_type| key_type.algorithm())
+                    .find(|algorithm| key_pair.is_compatible(algorithm))
+                    .ok_or(Error::KeyLoad("unsupported key type".to_string()))?;
+
+                Ok((key_pair, algorithm))
+            },
+
+            Self::Csr(_) => Err(Error::KeyLoad("an external CSR comes without a private key".to_string())),
+        }
+    }
+}
+
+/// The DNS names and IP addresses the DER encoded Certificate Signing Request asks for.
+fn csr_names(csr_der: &[u8]) -> Result<Vec<String>, Error> {
+    let csr = CertReq::from_der(csr_der)
+        .map_err(|e| Error::CsrParse(e.to_string()))?;
+
+    let mut names = Vec::new();
+    for attribute in csr.info.attributes.iter().filter(|attribute| attribute.oid == ExtensionReq::OID) {
+        for value in attribute.values.iter() {
+            let extensions = value.to_der()
+                .and_then(|value_der| ExtensionReq::from_der(&value_der))
+                .map_err(|e| Error::CsrParse(e.to_string()))?;
+
+            for extension in extensions.0.iter().filter(|extension| extension.extn_id == SubjectAltName::OID) {
+                let san = SubjectAltName::from_der(extension.extn_value.as_bytes())
+                    .map_err(|e| Error::CsrParse(e.to_string()))?;
+
+                names.extend(general_names(san.0));
+            }
+        }
+    }
+
+    Ok(names)
+}
@@ -2,88 +2,359 @@ pub mod signed_json;
 pub mod account;
 pub mod order;
 pub mod authorization;
+pub mod revocation;
+pub mod problem;
+pub mod poller;
+pub mod renewal;
+pub mod key;
 
 use serde::{Serialize, Deserialize};
 use ureq::Response;
 use sha2::{Digest, Sha256};
 use base64ct::{Base64UrlUnpadded, Encoding};
+use std::sync::{Arc, OnceLock};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::time::Duration;
+use time::OffsetDateTime;
+use time::format_description::well_known::{Rfc2822, Rfc3339};
+use problem::Problem;
+use rsa::pkcs8::{DecodePrivateKey, EncodePrivateKey};
+use rsa::traits::PublicKeyParts;
+use x509_cert::ext::pkix::name::GeneralName;
 
 pub(crate) const USIZE_LEN: usize = std::mem::size_of::<usize>();
 
+/// How many times to retry getting a nonce, or a request the server rejected with `badNonce`.
+pub(crate) const NONCE_RETRIES: usize = 3;
+
+/// How much longer to wait before each of those retries, unless the server says otherwise.
+const NONCE_BACKOFF: Duration = Duration::from_millis(500);
+
+/// The longest `Retry-After` suggestion to follow between those retries.
+const MAX_NONCE_BACKOFF: Duration = Duration::from_secs(10);
+
+/// The HTTP agent used for all requests to the Certificate Authority.
+static AGENT: OnceLock<ureq::Agent> = OnceLock::new();
+
 /// Consolidates errors of a few types.
 #[derive(Debug)]
 pub enum Error {
     HttpIo(String),
-    HttpGet(Response),
-    HttpHead(Response),
-    HttpPost(Response),
+    HttpGet(Box<Problem>),
+    HttpHead(Box<Problem>),
+    HttpPost(Box<Problem>),
     ResponseIntoJson(String),
     ResponseIntoString(String),
-    ResponseLacksLocation(Response),
+    ResponseLacksLocation,
     CertificateSerialize(String),
     CertificateUnavailable,
     JsonToVec(String),
     JsonFromBytes(String),
     SigningKeyFromBytes(String),
     ParseFromBytes(String),
+    TermsOfServiceNotAgreed(String),
+    AccountUrlUnavailable,
+    KeyChangeUnavailable,
+    AccountStatus(String),
+    UnknownRevocationReason(String),
+    PemDecode(String),
+    TrustRoots(String),
+    ExternalAccountRequired,
+    InvalidHmacKey(String),
+    NonceUnavailable,
+    UnsupportedChallenge(String),
+    NoUsableChallenge(String, String),
+    CertificateParse(String),
+    RenewalInfo(String),
+    UnknownProfile(String, String),
+    KeyGeneration(String),
+    KeyLoad(String),
+    CsrParse(String),
+    CsrMismatch(String),
+    CommonNameMismatch(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::HttpIo(e) => write!(f, "HTTP request failed: {e}"),
+            Self::HttpGet(problem) => write!(f, "GET request failed: {problem}"),
+            Self::HttpHead(problem) => write!(f, "HEAD request failed: {problem}"),
+            Self::HttpPost(problem) => write!(f, "POST request failed: {problem}"),
+            Self::ResponseIntoJson(e) => write!(f, "Failed to parse JSON response: {e}"),
+            Self::ResponseIntoString(e) => write!(f, "Failed to read response: {e}"),
+            Self::ResponseLacksLocation => write!(f, "Response lacks a 'Location' header"),
+            Self::CertificateSerialize(e) => write!(f, "Failed to serialize certificate request: {e}"),
+            Self::CertificateUnavailable => write!(f, "Certificate is not available yet"),
+            Self::JsonToVec(e) => write!(f, "Failed to serialize JSON: {e}"),
+            Self::JsonFromBytes(e) => write!(f, "Failed to deserialize JSON: {e}"),
+            Self::SigningKeyFromBytes(e) => write!(f, "Invalid signing key: {e}"),
+            Self::ParseFromBytes(e) => write!(f, "Failed to parse account ({e})"),
+            Self::TermsOfServiceNotAgreed(url) => write!(f, "Terms of service ({url}) were not agreed to"),
+            Self::AccountUrlUnavailable => write!(f, "Account URL is unknown"),
+            Self::KeyChangeUnavailable => write!(f, "Certificate Authority does not support key changes"),
+            Self::AccountStatus(status) => write!(f, "Account is {status}"),
+            Self::UnknownRevocationReason(reason) => write!(f, "Unknown revocation reason '{reason}'"),
+            Self::PemDecode(e) => write!(f, "Failed to decode PEM data: {e}"),
+            Self::TrustRoots(e) => write!(f, "Failed to trust root certificates: {e}"),
+            Self::ExternalAccountRequired => write!(f, "Certificate Authority requires an external account binding"),
+            Self::InvalidHmacKey(e) => write!(f, "Invalid external account binding HMAC key: {e}"),
+            Self::NonceUnavailable => write!(f, "Server did not provide a nonce"),
+            Self::UnsupportedChallenge(challenge) => write!(f, "Unsupported challenge type '{challenge}'"),
+            Self::NoUsableChallenge(identifier, offered) => write!(f, "None of the challenge types configured for {identifier} are offered (offered: {offered})"),
+            Self::CertificateParse(e) => write!(f, "Failed to parse certificate: {e}"),
+            Self::RenewalInfo(e) => write!(f, "Invalid renewal information: {e}"),
+            Self::UnknownProfile(profile, available) => write!(f, "Certificate Authority does not offer the '{profile}' profile (offered: {available})"),
+            Self::KeyGeneration(e) => write!(f, "Failed to generate certificate key: {e}"),
+            Self::KeyLoad(e) => write!(f, "Failed to load certificate key: {e}"),
+            Self::CsrParse(e) => write!(f, "Failed to parse certificate signing request: {e}"),
+            Self::CsrMismatch(names) => write!(f, "Certificate signing request names ({names}) don't match the order"),
+            Self::CommonNameMismatch(name) => write!(f, "Common name '{name}' is not one of the order's identifiers"),
+        }
+    }
 }
 
+impl std::error::Error for Error {}
+
 /// Supported ACME challenge types.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
 pub enum ChallengeType {
+    #[serde(rename = "dns-01", alias = "dns")]
     DNS,
+    #[serde(rename = "http-01", alias = "http")]
+    Http,
+    #[serde(rename = "tls-alpn-01", alias = "tls-alpn")]
+    TlsAlpn,
 }
 
 impl Into<&str> for ChallengeType {
     fn into(self) -> &'static str {
         match self {
             Self::DNS => "dns-01",
+            Self::Http => "http-01",
+            Self::TlsAlpn => "tls-alpn-01",
+        }
+    }
+}
+
+impl TryFrom<&str> for ChallengeType {
+    type Error = Error;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s {
+            "dns-01" => Ok(Self::DNS),
+            "http-01" => Ok(Self::Http),
+            "tls-alpn-01" => Ok(Self::TlsAlpn),
+            _ => Err(Error::UnsupportedChallenge(s.to_string())),
         }
     }
 }
 
+/// Supported certificate key types.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+pub enum KeyType {
+    #[default]
+    #[serde(rename = "p256", alias = "P-256", alias = "ecdsa-p256")]
+    P256,
+    #[serde(rename = "p384", alias = "P-384", alias = "ecdsa-p384")]
+    P384,
+    #[serde(rename = "rsa2048", alias = "RSA2048")]
+    Rsa2048,
+    #[serde(rename = "rsa3072", alias = "RSA3072")]
+    Rsa3072,
+    #[serde(rename = "rsa4096", alias = "RSA4096")]
+    Rsa4096,
+    #[serde(rename = "ed25519", alias = "Ed25519")]
+    Ed25519,
+}
+
+impl KeyType {
+    /// The signature algorithm used with keys of this type.
+    fn algorithm(self) -> &'static rcgen::SignatureAlgorithm {
+        match self {
+            Self::P256 => &rcgen::PKCS_ECDSA_P256_SHA256,
+            Self::P384 => &rcgen::PKCS_ECDSA_P384_SHA384,
+            Self::Rsa2048 | Self::Rsa3072 | Self::Rsa4096 => &rcgen::PKCS_RSA_SHA256,
+            Self::Ed25519 => &rcgen::PKCS_ED25519,
+        }
+    }
+
+    /// The modulus size of RSA keys of this type.
+    fn rsa_bits(self) -> Option<usize> {
+        match self {
+            Self::Rsa2048 => Some(2048),
+            Self::Rsa3072 => Some(3072),
+            Self::Rsa4096 => Some(4096),
+            Self::P256 | Self::P384 | Self::Ed25519 => None,
+        }
+    }
+
+    /// Whether the PKCS#8 DER encoded private key is of this type (and size).
+    pub fn matches(self, key_der: &[u8]) -> bool {
+        match self.rsa_bits() {
+            Some(bits) => rsa::RsaPrivateKey::from_pkcs8_der(key_der)
+                .is_ok_and(|key| key.size() * 8 == bits),
+            None => rcgen::KeyPair::from_der(key_der)
+                .is_ok_and(|key_pair| key_pair.is_compatible(self.algorithm())),
+        }
+    }
+
+    /// Generate a new key of this type.
+    fn generate(self) -> Result<rcgen::KeyPair, Error> {
+        let Some(bits) = self.rsa_bits() else {
+            return rcgen::KeyPair::generate(self.algorithm())
+                .map_err(|e| Error::KeyGeneration(e.to_string()));
+        };
+
+        // rcgen can't generate RSA keys itself, but can use them.
+        let key = rsa::RsaPrivateKey::new(&mut rand::rngs::OsRng, bits)
+            .map_err(|e| Error::KeyGeneration(e.to_string()))?;
+        let key_der = key.to_pkcs8_der()
+            .map_err(|e| Error::KeyGeneration(e.to_string()))?;
+
+        rcgen::KeyPair::from_der(key_der.as_bytes())
+            .map_err(|e| Error::KeyGeneration(e.to_string()))
+    }
+}
+
+impl fmt::Display for KeyType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::P256 => "p256",
+            Self::P384 => "p384",
+            Self::Rsa2048 => "rsa2048",
+            Self::Rsa3072 => "rsa3072",
+            Self::Rsa4096 => "rsa4096",
+            Self::Ed25519 => "ed25519",
+        })
+    }
+}
+
+/// Credentials given by a Certificate Authority to bind a new ACME account to an existing account
+/// of theirs. ([RFC 8555§7.3.4](https://www.rfc-editor.org/rfc/rfc8555.html#section-7.3.4))
+#[derive(Debug, Deserialize)]
+pub struct ExternalAccountBinding {
+    pub kid: String,
+    /// The base64url encoded HMAC key.
+    pub hmac_key: String,
+}
+
 /// An enum of supported Certificate Authority ACME APIs.
+#[derive(Debug, Clone)]
 pub enum CertificateAuthority {
     LetsEncryptStaging,
     LetsEncryptProduction,
+    ZeroSsl,
+    BuypassStaging,
+    BuypassProduction,
+    GoogleStaging,
+    GoogleProduction,
+    SslComRsa,
+    SslComEcc,
+
+    /// Any other ACME server (e.g. step-ca), given its directory URL.
+    Custom(String),
 }
 
-impl Into<&str> for CertificateAuthority {
-    fn into(self) -> &'static str {
+impl CertificateAuthority {
+    /// The URL of the Certificate Authority's ACME directory.
+    pub fn url(&self) -> &str {
         match self {
             Self::LetsEncryptStaging => "https://acme-staging-v02.api.letsencrypt.org/directory",
             Self::LetsEncryptProduction => "https://acme-v02.api.letsencrypt.org/directory",
+            Self::ZeroSsl => "https://acme.zerossl.com/v2/DV90",
+            Self::BuypassStaging => "https://api.test4.buypass.no/acme/directory",
+            Self::BuypassProduction => "https://api.buypass.com/acme/directory",
+            Self::GoogleStaging => "https://dv.acme-v02.test-api.pki.goog/directory",
+            Self::GoogleProduction => "https://dv.acme-v02.api.pki.goog/directory",
+            Self::SslComRsa => "https://acme.ssl.com/sslcom-dv-rsa",
+            Self::SslComEcc => "https://acme.ssl.com/sslcom-dv-ecc",
+            Self::Custom(url) => url,
         }
     }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 #[serde(untagged)]
 pub(crate) enum Payload<'a> {
     #[serde(rename_all = "camelCase")]
     NewAccount {
         contact: &'a [String],
         terms_of_service_agreed: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        external_account_binding: Option<signed_json::SignedJson>,
+    },
+
+    UpdateAccount {
+        contact: &'a [String],
     },
 
+    #[serde(rename_all = "camelCase")]
+    KeyChange {
+        account: &'a str,
+        old_key: &'a signed_json::Jwk,
+    },
+
+    UpdateStatus {
+        status: &'static str,
+    },
+
+    Nested(&'a signed_json::SignedJson),
+
+    Jwk(&'a signed_json::Jwk),
+
+    #[serde(rename_all = "camelCase")]
     NewOrder {
         identifiers: &'a [Identifier],
+        #[serde(skip_serializing_if = "Option::is_none")]
+        replaces: Option<&'a str>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        profile: Option<&'a str>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        not_before: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        not_after: Option<String>,
     },
 
     Finalize {
         csr: String,
     },
 
+    RevokeCert {
+        certificate: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reason: Option<u8>,
+    },
+
     EmptyObject {},
 
     Empty
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub(crate) struct Identifier {
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Identifier {
     pub r#type: String,
     pub value: String,
 }
 
+impl Identifier {
+    /// An identifier for the domain name or IP address, the latter being of type "ip".
+    /// ([RFC 8738§3](https://www.rfc-editor.org/rfc/rfc8738.html#section-3))
+    pub fn new(value: &str) -> Self {
+        let r#type = match value.parse::<IpAddr>() {
+            Ok(_) => "ip",
+            Err(_) => "dns",
+        };
+
+        Self {
+            r#type: r#type.to_string(),
+            value: value.to_string(),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub(crate) struct Directory {
@@ -91,10 +362,24 @@ pub(crate) struct Directory {
     pub new_account: String,
     pub new_order: String,
     pub revoke_cert: String,
+    pub key_change: Option<String>,
+    pub renewal_info: Option<String>,
+    pub meta: Option<DirectoryMeta>,
 
     pub account: Option<String>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct DirectoryMeta {
+    pub terms_of_service: Option<String>,
+    pub website: Option<String>,
+    pub caa_identities: Option<Vec<String>>,
+    pub external_account_required: Option<bool>,
+    /// Certificate profile names, with a description of each.
+    pub profiles: Option<HashMap<String, String>>,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct Challenge {
     pub r#type: String,
@@ -102,6 +387,7 @@ pub struct Challenge {
     pub token: String,
     pub status: Option<String>,
     pub validated: Option<String>,
+    pub error: Option<Problem>,
     #[serde(skip)]
     pub domain: String,
     #[serde(skip)]
@@ -118,30 +404,191 @@ fn from_json_bytes<'a, T: Deserialize<'a>>(data: &'a [u8]) -> Result<T, Error> {
         .map_err(|e| Error::JsonFromBytes(e.to_string()))
 }
 
+/// Decode the PEM encoded data into the DER bytes of each block with the given label
+/// (e.g. "CERTIFICATE"), in order of appearance.
+pub fn decode_pem(pem: &str, label: &str) -> Result<Vec<Vec<u8>>, Error> {
+    let begin = format!("-----BEGIN {label}-----");
+    let end = format!("-----END {label}-----");
+
+    let mut blocks = Vec::new();
+    let mut remaining = pem;
+    while let Some(start) = remaining.find(&begin) {
+        let block = &remaining[start + begin.len()..];
+        let stop = block.find(&end)
+            .ok_or(Error::PemDecode(format!("unterminated {label} block")))?;
+
+        let encoded: String = block[..stop].chars()
+            .filter(|c| !c.is_ascii_whitespace())
+            .collect();
+        blocks.push(base64ct::Base64::decode_vec(&encoded)
+            .map_err(|e| Error::PemDecode(e.to_string()))?);
+
+        remaining = &block[stop + end.len()..];
+    }
+
+    Ok(blocks)
+}
+
+/// Encode the DER bytes as a PEM block with the given label (e.g. "PRIVATE KEY").
+pub fn encode_pem(label: &str, der: &[u8]) -> String {
+    let encoded = base64ct::Base64::encode_string(der);
+    let lines: Vec<&str> = encoded.as_bytes()
+        .chunks(64)
+        .map(|line| std::str::from_utf8(line).unwrap_or_default())
+        .collect();
+
+    format!("-----BEGIN {label}-----\n{}\n-----END {label}-----\n", lines.join("\n"))
+}
+
+/// The DNS names and IP addresses among the names (e.g. of a Subject Alternative Name extension).
+fn general_names(names: Vec<GeneralName>) -> Vec<String> {
+    names.into_iter()
+        .filter_map(|name| match name {
+            GeneralName::DnsName(dns_name) => Some(dns_name.to_string()),
+            GeneralName::IpAddress(ip) => match ip.as_bytes().len() {
+                4 => <[u8; 4]>::try_from(ip.as_bytes()).ok().map(|ip| IpAddr::from(ip).to_string()),
+                16 => <[u8; 16]>::try_from(ip.as_bytes()).ok().map(|ip| IpAddr::from(ip).to_string()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+/// Whether both lists hold the same names, ignoring order, duplicates and case.
+fn same_names<A: AsRef<str>, B: AsRef<str>>(a: &[A], b: &[B]) -> bool {
+    let normalize = |names: &mut Vec<String>| {
+        names.sort();
+        names.dedup();
+    };
+
+    let mut a: Vec<String> = a.iter()
+        .map(|name| name.as_ref().to_ascii_lowercase())
+        .collect();
+    let mut b: Vec<String> = b.iter()
+        .map(|name| name.as_ref().to_ascii_lowercase())
+        .collect();
+    normalize(&mut a);
+    normalize(&mut b);
+
+    a == b
+}
+
+/// Trust the given DER encoded root certificates in addition to the bundled ones, e.g. for an
+/// internal Certificate Authority. Must be called before any request is made.
+pub fn trust_root_certificates(roots: Vec<Vec<u8>>) -> Result<(), Error> {
+    let mut root_store = rustls::RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+    for root in roots {
+        root_store.add(root.into())
+            .map_err(|e| Error::TrustRoots(e.to_string()))?;
+    }
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let tls_config = rustls::ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(|e| Error::TrustRoots(e.to_string()))?
+        .with_root_certificates(root_store)
+        .with_no_client_auth();
+
+    let agent = ureq::AgentBuilder::new()
+        .tls_config(Arc::new(tls_config))
+        .build();
+
+    AGENT.set(agent)
+        .map_err(|_| Error::TrustRoots("requests were already made".to_string()))
+}
+
+fn agent() -> &'static ureq::Agent {
+    AGENT.get_or_init(ureq::agent)
+}
+
 /// Make a simple HTTP HEAD request, returning the value of the given header.
 fn http_head(url: &str) -> Result<Response, Error> {
-    ureq::head(url)
+    agent().head(url)
         .call()
             .map_err(|e| {
                 let e_str = e.to_string();
 
                 if let Some(response) = e.into_response() {
-                    Error::HttpHead(response)
+                    Error::HttpHead(Box::new(Problem::from(response)))
                 } else {
                     Error::HttpIo(e_str)
                 }
             })
 }
 
+/// Ask the server for a fresh nonce, retrying a few times if that fails.
+/// ([RFC 8555§7.2](https://www.rfc-editor.org/rfc/rfc8555.html#section-7.2))
+fn new_nonce(url: &str) -> Result<String, Error> {
+    let mut retries = 0;
+    loop {
+        let result = http_head(url)
+            .and_then(|response| response.header("replay-nonce")
+                .map(|s| s.to_string())
+                .ok_or(Error::NonceUnavailable));
+
+        match result {
+            Err(e) if retries < NONCE_RETRIES => {
+                retries += 1;
+
+                let retry_after = match e {
+                    Error::HttpHead(problem) => problem.retry_after,
+                    _ => None,
+                };
+                std::thread::sleep(nonce_backoff(retries, retry_after));
+            },
+
+            result => return result,
+        }
+    }
+}
+
+/// How long to wait before the given retry of a nonce request or a request rejected with `badNonce`:
+/// the server's `Retry-After` suggestion (up to [MAX_NONCE_BACKOFF]), or a growing delay otherwise.
+pub(crate) fn nonce_backoff(retry: usize, retry_after: Option<Duration>) -> Duration {
+    retry_after.map_or(NONCE_BACKOFF * retry as u32, |retry_after| retry_after.min(MAX_NONCE_BACKOFF))
+}
+
+/// Read the `Retry-After` header of the response, given either in seconds or as an HTTP date.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.header("retry-after")?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = OffsetDateTime::parse(value, &Rfc2822).ok()?;
+    let seconds = (date - OffsetDateTime::now_utc()).whole_seconds();
+
+    Some(Duration::from_secs(seconds.max(0) as u64))
+}
+
+/// The URLs of the response's `Link` headers with the given relation type (e.g. "alternate").
+/// ([RFC 8288§3](https://www.rfc-editor.org/rfc/rfc8288.html#section-3))
+fn links(response: &Response, rel: &str) -> Vec<String> {
+    response.all("link").iter()
+        .flat_map(|header| header.split(','))
+        .filter_map(|link| {
+            let (url, params) = link.trim().strip_prefix('<')?.split_once('>')?;
+            let is_rel = params.split(';')
+                .filter_map(|param| param.split_once('='))
+                .any(|(name, value)| name.trim() == "rel" && value.trim().trim_matches('"') == rel);
+
+            is_rel.then(|| url.to_string())
+        })
+        .collect()
+}
+
 /// Make a simple HTTP GET request.
 fn http_get(url: &str) -> Result<Response, Error> {
-    ureq::get(url)
+    agent().get(url)
         .call()
             .map_err(|e| {
                 let e_str = e.to_string();
 
                 if let Some(response) = e.into_response() {
-                    Error::HttpGet(response)
+                    Error::HttpGet(Box::new(Problem::from(response)))
                 } else {
                     Error::HttpIo(e_str)
                 }
@@ -149,14 +596,14 @@ fn http_get(url: &str) -> Result<Response, Error> {
 }
 
 fn http_post(url: &str, signed_json: signed_json::SignedJson) -> Result<Response, Error> {
-    ureq::post(url)
+    agent().post(url)
         .set("content-type", "application/jose+json")
         .send_json(signed_json)
             .map_err(|e| {
                 let e_str = e.to_string();
 
                 if let Some(response) = e.into_response() {
-                    Error::HttpPost(response)
+                    Error::HttpPost(Box::new(Problem::from(response)))
                 } else {
                     Error::HttpIo(e_str)
                 }
@@ -168,4 +615,82 @@ fn get_as_json<T: for<'a> Deserialize<'a>>(url: &str) -> Result<T, Error> {
     http_get(url)?
         .into_json()
             .map_err(|e| Error::ResponseIntoJson(e.to_string()))
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use x509_cert::der::asn1::{Ia5String, OctetString};
+
+    #[test]
+    fn same_names_ignores_order_duplicates_and_case() {
+        assert!(same_names(&["a.example", "B.example"], &["b.example", "a.example", "A.EXAMPLE"]));
+        assert!(same_names::<&str, &str>(&[], &[]));
+
+        assert!(!same_names(&["a.example"], &["a.example", "b.example"]));
+        assert!(!same_names(&["a.example"], &["*.a.example"]));
+        assert!(!same_names(&["a.example"], &[] as &[&str]));
+    }
+
+    #[test]
+    fn general_names_keeps_dns_names_and_ip_addresses() {
+        let names = vec![
+            GeneralName::DnsName(Ia5String::new("a.example").unwrap()),
+            GeneralName::from("192.0.2.1".parse::<IpAddr>().unwrap()),
+            GeneralName::from("2001:db8::1".parse::<IpAddr>().unwrap()),
+            GeneralName::Rfc822Name(Ia5String::new("admin@a.example").unwrap()),
+            // Neither an IPv4 nor an IPv6 address.
+            GeneralName::IpAddress(OctetString::new(vec![192, 0, 2]).unwrap()),
+        ];
+
+        assert_eq!(general_names(names), ["a.example", "192.0.2.1", "2001:db8::1"]);
+    }
+
+    #[test]
+    fn encode_pem_wraps_at_64_columns() {
+        // 100 bytes encode to 136 characters: two full lines and one of 8.
+        let pem = encode_pem("CERTIFICATE", &[0xab; 100]);
+        let lines: Vec<&str> = pem.lines().collect();
+        assert_eq!(lines.first(), Some(&"-----BEGIN CERTIFICATE-----"));
+        assert_eq!(lines.last(), Some(&"-----END CERTIFICATE-----"));
+        assert_eq!(lines[1..lines.len() - 1].iter().map(|line| line.len()).collect::<Vec<_>>(), [64, 64, 8]);
+        assert!(pem.ends_with("-----\n"));
+
+        // 48 bytes encode to exactly 64 characters, without an empty line after them.
+        let pem = encode_pem("CERTIFICATE", &[0xab; 48]);
+        assert_eq!(pem.lines().count(), 3);
+        assert!(!pem.contains("\n\n"));
+    }
+
+    #[test]
+    fn encode_pem_round_trips_through_decode_pem() {
+        for len in [0, 1, 2, 3, 47, 48, 49, 1000] {
+            let der: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let pem = encode_pem("PRIVATE KEY", &der);
+            assert_eq!(decode_pem(&pem, "PRIVATE KEY").unwrap(), [der]);
+            assert!(decode_pem(&pem, "CERTIFICATE").unwrap().is_empty());
+        }
+
+        let chain = encode_pem("CERTIFICATE", b"leaf") + &encode_pem("CERTIFICATE", b"intermediate");
+        assert_eq!(decode_pem(&chain, "CERTIFICATE").unwrap(), [b"leaf".to_vec(), b"intermediate".to_vec()]);
+    }
+
+    #[test]
+    fn key_type_matches_only_keys_of_its_type_and_size() {
+        let p256 = KeyType::P256.generate().unwrap().serialize_der();
+        assert!(KeyType::P256.matches(&p256));
+        assert!(!KeyType::P384.matches(&p256));
+        assert!(!KeyType::Ed25519.matches(&p256));
+        assert!(!KeyType::Rsa2048.matches(&p256));
+
+        let ed25519 = KeyType::Ed25519.generate().unwrap().serialize_der();
+        assert!(KeyType::Ed25519.matches(&ed25519));
+        assert!(!KeyType::P256.matches(&ed25519));
+
+        let rsa2048 = KeyType::Rsa2048.generate().unwrap().serialize_der();
+        assert!(KeyType::Rsa2048.matches(&rsa2048));
+        assert!(!KeyType::Rsa3072.matches(&rsa2048));
+        assert!(!KeyType::P256.matches(&rsa2048));
+
+        assert!(!KeyType::P256.matches(b"not a key"));
+    }
+}
@@ -1,9 +1,15 @@
 use super::*;
 use super::account::*;
 use super::authorization::*;
+use super::problem::*;
+use super::key::*;
 
-use rcgen::{CertificateParams, DistinguishedName};
+use rcgen::{CertificateParams, CustomExtension, DistinguishedName, DnType, SanType};
 use rcgen::Certificate;
+use std::net::IpAddr;
+use std::time::Duration;
+use x509_cert::der::Decode;
+use x509_cert::der::oid::db::rfc4519::COMMON_NAME;
 
 pub enum OrderStatus {
     Invalid,
@@ -33,6 +39,34 @@ pub(crate) struct OrderData {
     pub authorizations: Vec<String>,
     pub finalize: String,
     pub certificate: Option<String>,
+    pub error: Option<Problem>,
+}
+
+/// The `id-pe-tlsfeature` extension OID. ([RFC 7633§6](https://www.rfc-editor.org/rfc/rfc7633.html#section-6))
+const OID_TLS_FEATURE: &[u64] = &[1, 3, 6, 1, 5, 5, 7, 1, 24];
+
+/// The TLS extension number of `status_request`. ([RFC 6066§8](https://www.rfc-editor.org/rfc/rfc6066.html#section-8))
+const STATUS_REQUEST: u8 = 5;
+
+/// Optional details of a new [Order]. ([RFC 8555§7.4](https://www.rfc-editor.org/rfc/rfc8555.html#section-7.4))
+#[derive(Debug, Default)]
+pub struct OrderOptions<'a> {
+    /// The ID of the certificate this order replaces (see [IssuedCertificate](super::renewal::IssuedCertificate)).
+    pub replaces: Option<&'a str>,
+    /// One of the profiles the Certificate Authority lists in its directory (e.g. "shortlived").
+    pub profile: Option<&'a str>,
+    pub not_before: Option<OffsetDateTime>,
+    pub not_after: Option<OffsetDateTime>,
+}
+
+/// Optional contents of the Certificate Signing Request generated by [Order::finalize].
+#[derive(Debug, Default)]
+pub struct CsrOptions<'a> {
+    /// Require OCSP stapling ("Must-Staple") through the TLS Feature extension.
+    /// ([RFC 7633](https://www.rfc-editor.org/rfc/rfc7633.html))
+    pub must_staple: bool,
+    /// The subject's common name, which must be one of the order's identifiers.
+    pub common_name: Option<&'a str>,
 }
 
 pub struct Order<'a> {
@@ -40,34 +74,63 @@ pub struct Order<'a> {
     pub(crate) data: OrderData,
     pub(crate) account: &'a Account,
     pub(crate) certificate: Option<Certificate>,
+    pub(crate) retry_after: Option<Duration>,
+    pub(crate) chain_errors: Vec<Error>,
 }
 
 impl<'a> Order<'a> {
-    /// Retrieve [Authorization]s for this [Order], returning only those that match the given [ChallengeType].
-    pub fn authorize(&self, ct: ChallengeType) -> Result<Vec<Authorization>, Error> {
-        let challenge_type: &str = ct.into();
+    /// Retrieve the [Authorization]s of this [Order] that still need to be proven, each with the first
+    /// challenge offered among the [ChallengeType]s preferred for its identifier (in order).
+    /// Fails if any of them offers none of the preferred types.
+    pub fn authorize<F>(&self, preferences: F) -> Result<Vec<Authorization<'_>>, Error>
+    where
+        F: Fn(&str) -> Vec<ChallengeType>,
+    {
         let mut authorizations = Vec::new();
         for auth_url in &self.data.authorizations {
             let auth_data: AuthData = self.account.post_as_json(&auth_url, Payload::Empty)?;
-            
-            for challenge in &auth_data.challenges {
-                if challenge.r#type == challenge_type {
-                    let mut challenge = challenge.clone();
-                    let response = format!("{}.{}", challenge.token,
-                        self.account.crypto.thumbprint);
-                    
-                    challenge.domain = auth_data.identifier.value.clone();
-                    challenge.response = Base64UrlUnpadded::encode_string(&Sha256::digest(response));
-
-                    authorizations.push(Authorization {
-                        url: auth_url,
-                        data: auth_data,
-                        challenge: challenge,
-                    });
-
-                    break;
-                }
+
+            // Already proven (e.g. by an earlier order), nothing to do.
+            if let AuthStatus::Valid = AuthStatus::from(auth_data.status.as_str()) {
+                continue;
             }
+
+            let identifier = auth_data.name();
+            let offered = preferences(&identifier).into_iter()
+                .find_map(|ct| {
+                    let challenge_type: &str = ct.into();
+
+                    auth_data.challenges.iter()
+                        .find(|challenge| challenge.r#type == challenge_type)
+                        .map(|challenge| (ct, challenge.clone()))
+                });
+
+            let Some((ct, mut challenge)) = offered else {
+                let offered: Vec<&str> = auth_data.challenges.iter()
+                    .map(|challenge| challenge.r#type.as_str())
+                    .collect();
+
+                return Err(Error::NoUsableChallenge(identifier, offered.join(", ")));
+            };
+
+            let key_authorization = format!("{}.{}", challenge.token,
+                self.account.crypto.thumbprint);
+
+            challenge.domain = identifier;
+            challenge.response = match ct {
+                // The TXT record holds a digest of the key authorization. (RFC 8555§8.4)
+                ChallengeType::DNS => Base64UrlUnpadded::encode_string(&Sha256::digest(key_authorization)),
+                // The key authorization is served as is. (RFC 8555§8.3)
+                ChallengeType::Http => key_authorization,
+                // The validation certificate carries a digest of it, computed by the responder. (RFC 8737§3)
+                ChallengeType::TlsAlpn => key_authorization,
+            };
+
+            authorizations.push(Authorization {
+                url: auth_url,
+                data: auth_data,
+                challenge,
+            });
         }
 
         Ok(authorizations)
@@ -87,51 +150,189 @@ impl<'a> Order<'a> {
         Ok(challenges)
     }
 
-    /// Check the current [Order] status.
+    /// The error that caused the [Order] to become invalid, if any.
+    pub fn error(&self) -> Option<&Problem> {
+        self.data.error.as_ref()
+    }
+
+    /// Collect the errors of failed challenges, naming the identifier each one failed for (as ordered,
+    /// see [AuthData::name]).
+    pub fn challenge_errors(&self) -> Result<Vec<Problem>, Error> {
+        let mut problems = Vec::new();
+        for auth_url in &self.data.authorizations {
+            let auth_data: AuthData = self.account.post_as_json(auth_url, Payload::Empty)?;
+            let identifier = Identifier {
+                r#type: auth_data.identifier.r#type.clone(),
+                value: auth_data.name(),
+            };
+
+            for challenge in auth_data.challenges {
+                if let Some(mut problem) = challenge.error {
+                    problem.identifier = Some(identifier.clone());
+                    problems.push(problem);
+                }
+            }
+        }
+
+        Ok(problems)
+    }
+
+    /// Check the current [Order] status. While it's pending, its authorizations are checked as well.
+    /// The server's longest `Retry-After` suggestion is kept, see [Order::retry_after].
     pub fn status(&mut self) -> Result<OrderStatus, Error> {
-        self.data = self.account.post_as_json(&self.url, Payload::Empty)?;
+        let response = self.account.post(&self.url, Payload::Empty)?;
+        self.retry_after = retry_after(&response);
+        self.data = response.into_json()
+            .map_err(|e| Error::ResponseIntoJson(e.to_string()))?;
+
+        let status = OrderStatus::from(self.data.status.as_str());
+        if let OrderStatus::Pending = status {
+            self.check_authorizations()?;
+        }
 
-        Ok(OrderStatus::from(self.data.status.as_str()))
+        Ok(status)
     }
 
-    /// Ask the server to finalize/complete the order and start generating a certificate.
-    pub fn finalize(&mut self) -> Result<(), Error> {
-        let identifiers: Vec<String> = self.data.identifiers.iter()
-            .map(|id| id.value.to_string())
-            .collect();
-        
-        // Generate a Certificate Signing Request.
-        let mut cert_params = CertificateParams::new(identifiers);
-        cert_params.distinguished_name = DistinguishedName::new();
-        self.certificate = Certificate::from_params(cert_params).ok();
-
-        if let Some(ref cert) = self.certificate {
-            let cert_der = cert.serialize_request_der()
-                .map_err(|e| Error::CertificateSerialize(e.to_string()))?;
-            let csr = Base64UrlUnpadded::encode_string(&cert_der);
-
-            self.data = self.account.post_as_json(&self.data.finalize,
-                Payload::Finalize { csr })?;
+    /// Keep the `Retry-After` suggestions of the authorizations still being validated, if longer.
+    /// ([RFC 8555§7.5.1](https://www.rfc-editor.org/rfc/rfc8555.html#section-7.5.1))
+    fn check_authorizations(&mut self) -> Result<(), Error> {
+        for auth_url in &self.data.authorizations {
+            let response = self.account.post(auth_url, Payload::Empty)?;
+            let auth_retry_after = retry_after(&response);
+            let auth_data: AuthData = response.into_json()
+                .map_err(|e| Error::ResponseIntoJson(e.to_string()))?;
+
+            if let AuthStatus::Pending = AuthStatus::from(auth_data.status.as_str()) {
+                self.retry_after = self.retry_after.max(auth_retry_after);
+            }
         }
 
         Ok(())
     }
 
-    /// Download the certificate, returning the PEM encoded certificate and DER encoded private key.
-    pub fn download(&self) -> Result<(String, Vec<u8>), Error> {
+    /// How long the server asked to wait before checking the [Order] status again, if it did.
+    /// ([RFC 8555§7.4](https://www.rfc-editor.org/rfc/rfc8555.html#section-7.4))
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.retry_after
+    }
+
+    /// The errors of alternate chains [Order::download] failed to retrieve, keeping the default chain
+    /// (or another alternate) instead.
+    pub fn chain_errors(&self) -> &[Error] {
+        &self.chain_errors
+    }
+
+    /// Ask the server to finalize/complete the order and start generating a certificate for the key.
+    /// The [CsrOptions] only apply to generated Certificate Signing Requests.
+    pub fn finalize(&mut self, key: &CertificateKey, options: &CsrOptions) -> Result<(), Error> {
+        let identifiers: Vec<&str> = self.data.identifiers.iter()
+            .map(|id| id.value.as_str())
+            .collect();
+        key.check_names(&identifiers)?;
+
+        let csr_der = match key {
+            CertificateKey::Csr(csr_der) => csr_der.clone(),
+
+            CertificateKey::Generate(_) | CertificateKey::Existing(_) => {
+                let (key_pair, algorithm) = key.key_pair()?;
+
+                // IP identifiers become IP address SANs. (RFC 8738§4)
+                let subject_alt_names = self.data.identifiers.iter()
+                    .map(|id| match id.value.parse::<IpAddr>() {
+                        Ok(ip) if id.r#type == "ip" => SanType::IpAddress(ip),
+                        _ => SanType::DnsName(id.value.to_string()),
+                    })
+                    .collect();
+
+                // Generate a Certificate Signing Request.
+                let mut cert_params = CertificateParams::default();
+                cert_params.alg = algorithm;
+                cert_params.key_pair = Some(key_pair);
+                cert_params.subject_alt_names = subject_alt_names;
+                cert_params.distinguished_name = DistinguishedName::new();
+
+                if let Some(common_name) = options.common_name {
+                    if !identifiers.contains(&common_name) {
+                        return Err(Error::CommonNameMismatch(common_name.to_string()));
+                    }
+
+                    cert_params.distinguished_name.push(DnType::CommonName, common_name);
+                }
+
+                // The TLS Feature extension, listing the status_request feature. (RFC 7633§4.2)
+                if options.must_staple {
+                    cert_params.custom_extensions.push(CustomExtension::from_oid_content(
+                        OID_TLS_FEATURE, vec![0x30, 0x03, 0x02, 0x01, STATUS_REQUEST]));
+                }
+
+                let cert = Certificate::from_params(cert_params)
+                    .map_err(|e| Error::CertificateSerialize(e.to_string()))?;
+                let csr_der = cert.serialize_request_der()
+                    .map_err(|e| Error::CertificateSerialize(e.to_string()))?;
+                self.certificate = Some(cert);
+
+                csr_der
+            },
+        };
+
+        let csr = Base64UrlUnpadded::encode_string(&csr_der);
+        self.data = self.account.post_as_json(&self.data.finalize,
+            Payload::Finalize { csr })?;
+
+        Ok(())
+    }
+
+    /// Download the certificate, returning the PEM encoded certificate and DER encoded private key
+    /// (unless an external Certificate Signing Request was used).
+    /// If a `preferred_chain` is given, the first of the alternate chains offered whose topmost
+    /// certificate was issued by that (common) name is chosen instead of the default one, if any.
+    /// ([RFC 8555§7.4.2](https://www.rfc-editor.org/rfc/rfc8555.html#section-7.4.2))
+    pub fn download(&mut self, preferred_chain: Option<&str>) -> Result<(String, Option<Vec<u8>>), Error> {
         if let OrderStatus::Valid = OrderStatus::from(self.data.status.as_str()) {
             if let Some(cert_url) = self.data.certificate.clone() {
                 let response = self.account.post(&cert_url, Payload::Empty)?;
-                let private_key = self.certificate.as_ref().unwrap()
-                    .serialize_private_key_der();
+                let alternates = links(&response, "alternate");
+                let private_key = self.certificate.as_ref()
+                    .map(|cert| cert.serialize_private_key_der());
 
-                let cert_pem = response.into_string()
+                let mut cert_pem = response.into_string()
                     .map_err(|e| Error::ResponseIntoString(e.to_string()))?;
-                
+
+                if let Some(preferred_chain) = preferred_chain {
+                    if top_issuer_common_name(&cert_pem).as_deref() != Some(preferred_chain) {
+                        for alternate_url in alternates {
+                            let alternate_pem = self.account.post(&alternate_url, Payload::Empty)
+                                .and_then(|response| response.into_string()
+                                    .map_err(|e| Error::ResponseIntoString(e.to_string())));
+
+                            match alternate_pem {
+                                Ok(alternate_pem) if top_issuer_common_name(&alternate_pem).as_deref() == Some(preferred_chain) => {
+                                    cert_pem = alternate_pem;
+                                    break;
+                                },
+                                Ok(_) => {},
+                                Err(e) => self.chain_errors.push(e),
+                            }
+                        }
+                    }
+                }
+
                 return Ok((cert_pem, private_key));
             }
         }
 
         Err(Error::CertificateUnavailable)
     }
-}
\ No newline at end of file
+}
+
+/// The common name of the issuer of the last (i.e. closest to the root) certificate in the PEM encoded chain.
+fn top_issuer_common_name(chain_pem: &str) -> Option<String> {
+    let top_der = decode_pem(chain_pem, "CERTIFICATE").ok()?.pop()?;
+    let top = x509_cert::Certificate::from_der(&top_der).ok()?;
+
+    top.tbs_certificate.issuer.0.iter()
+        .flat_map(|rdn| rdn.0.iter())
+        .find(|attribute| attribute.oid == COMMON_NAME)
+        .and_then(|attribute| std::str::from_utf8(attribute.value.value()).ok())
+        .map(|name| name.to_string())
+}
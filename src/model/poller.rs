@@ -0,0 +1,47 @@
+use std::time::{Duration, Instant};
+
+/// The shortest wait between checks, however short the configured interval or `Retry-After` suggestion.
+const MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Paces repeated status checks (e.g. of an [Order](super::order::Order)) until a deadline passes.
+/// The server's `Retry-After` suggestion is preferred over the poller's own, growing, interval.
+pub struct Poller {
+    deadline: Instant,
+    step: Duration,
+    interval: Duration,
+    max_interval: Duration,
+}
+
+impl Poller {
+    /// Give up after `timeout`, first waiting `interval` between checks, then growing by `interval`
+    /// each time up to `max_interval`.
+    pub fn new(timeout: Duration, interval: Duration, max_interval: Duration) -> Self {
+        let interval = interval.max(MIN_INTERVAL);
+
+        Self {
+            deadline: Instant::now() + timeout,
+            step: interval,
+            interval,
+            max_interval,
+        }
+    }
+
+    /// Wait before the next check, at most until the deadline, returning `false` instead if it already passed.
+    pub fn wait(&mut self, retry_after: Option<Duration>) -> bool {
+        let now = Instant::now();
+        if now >= self.deadline {
+            return false;
+        }
+
+        let wait_time = retry_after.unwrap_or(self.interval)
+            .max(MIN_INTERVAL)
+            .min(self.deadline - now);
+
+        std::thread::sleep(wait_time);
+
+        // Wait a little longer next time.
+        self.interval = (self.interval + self.step).min(self.max_interval);
+
+        true
+    }
+}
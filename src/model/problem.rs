@@ -0,0 +1,96 @@
+use super::*;
+
+use std::fmt;
+
+const ACME_ERROR_PREFIX: &str = "urn:ietf:params:acme:error:";
+
+/// An error reported by the ACME server, as an `application/problem+json` document.
+/// ([RFC 8555§6.7](https://www.rfc-editor.org/rfc/rfc8555.html#section-6.7),
+/// [RFC 7807](https://www.rfc-editor.org/rfc/rfc7807))
+#[derive(Debug, Clone, Deserialize)]
+pub struct Problem {
+    #[serde(default = "about_blank")]
+    pub r#type: String,
+    pub detail: Option<String>,
+    pub status: Option<u16>,
+    pub identifier: Option<Identifier>,
+    #[serde(default)]
+    pub subproblems: Vec<Problem>,
+
+    /// The fresh nonce sent along with the error response, if any.
+    #[serde(skip)]
+    pub(crate) nonce: Option<String>,
+    /// How long the server asked to wait before trying again, if it did.
+    #[serde(skip)]
+    pub(crate) retry_after: Option<Duration>,
+}
+
+fn about_blank() -> String {
+    "about:blank".to_string()
+}
+
+impl From<Response> for Problem {
+    /// Parse the problem document from an error response, or describe the response if it lacks one.
+    fn from(response: Response) -> Self {
+        let status = response.status();
+        let nonce = response.header("replay-nonce")
+            .map(|s| s.to_string());
+        let retry_after = retry_after(&response);
+        let is_problem = response.content_type() == "application/problem+json";
+        let body = response.into_string()
+            .unwrap_or_default();
+
+        let mut problem = match serde_json::from_str::<Problem>(&body) {
+            Ok(problem) if is_problem => problem,
+            _ => Problem {
+                r#type: about_blank(),
+                detail: Some(body).filter(|b| !b.trim().is_empty()),
+                status: Some(status),
+                identifier: None,
+                subproblems: Vec::new(),
+                nonce: None,
+                retry_after: None,
+            },
+        };
+        problem.nonce = nonce;
+        problem.retry_after = retry_after;
+
+        problem
+    }
+}
+
+impl Problem {
+    /// The ACME error type without its URN prefix (e.g. "rateLimited"), or the full type otherwise.
+    pub fn kind(&self) -> &str {
+        self.r#type.strip_prefix(ACME_ERROR_PREFIX)
+            .unwrap_or(&self.r#type)
+    }
+
+    /// Whether this is the given ACME error type (e.g. "badNonce").
+    pub fn is(&self, kind: &str) -> bool {
+        self.r#type.strip_prefix(ACME_ERROR_PREFIX) == Some(kind)
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref identifier) = self.identifier {
+            write!(f, "{}: ", identifier.value)?;
+        }
+
+        write!(f, "{}", self.kind())?;
+
+        if let Some(status) = self.status {
+            write!(f, " ({status})")?;
+        }
+        if let Some(ref detail) = self.detail {
+            write!(f, ": {detail}")?;
+        }
+
+        for subproblem in &self.subproblems {
+            write!(f, "\n  - {subproblem}")?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,263 @@
+use super::*;
+
+use rand::Rng;
+use rand::rngs::OsRng;
+use x509_cert::Certificate;
+use x509_cert::der::{Decode, Encode};
+use x509_cert::der::asn1::AnyRef;
+use x509_cert::ext::pkix::{AuthorityKeyIdentifier, SubjectAltName};
+
+/// What's needed to decide when to renew a previously issued certificate.
+#[derive(Debug)]
+pub struct IssuedCertificate {
+    /// The certificate's ACME Renewal Information ID, if it has an Authority Key Identifier.
+    pub id: Option<String>,
+    pub not_before: OffsetDateTime,
+    pub not_after: OffsetDateTime,
+    /// The DNS names and IP addresses the certificate is valid for.
+    pub names: Vec<String>,
+}
+
+impl TryFrom<&[u8]> for IssuedCertificate {
+    type Error = Error;
+
+    /// Parse the DER encoded certificate.
+    fn try_from(cert_der: &[u8]) -> Result<Self, Self::Error> {
+        let cert = Certificate::from_der(cert_der)
+            .map_err(|e| Error::CertificateParse(e.to_string()))?;
+        let validity = &cert.tbs_certificate.validity;
+
+        let names = cert.tbs_certificate.get::<SubjectAltName>()
+            .map_err(|e| Error::CertificateParse(e.to_string()))?
+            .map(|(_, san)| general_names(san.0))
+            .unwrap_or_default();
+
+        Ok(Self {
+            id: certificate_id(&cert).ok(),
+            not_before: OffsetDateTime::UNIX_EPOCH + validity.not_before.to_unix_duration(),
+            not_after: OffsetDateTime::UNIX_EPOCH + validity.not_after.to_unix_duration(),
+            names,
+        })
+    }
+}
+
+impl IssuedCertificate {
+    /// Whether the certificate is valid for exactly the given names, ignoring order and case.
+    pub fn has_names(&self, names: &[&str]) -> bool {
+        same_names(&self.names, names)
+    }
+
+    /// When the certificate is `renew_before` its expiry. By default that's 30 days, or a third of
+    /// its lifetime for short-lived certificates.
+    pub fn expiry_renewal_time(&self, renew_before: Option<time::Duration>) -> OffsetDateTime {
+        let renew_before = renew_before.unwrap_or_else(|| {
+            time::Duration::days(30).min((self.not_after - self.not_before) / 3)
+        });
+
+        self.not_after - renew_before
+    }
+}
+
+/// The unique identifier of a certificate for ACME Renewal Information requests, made of its
+/// Authority Key Identifier and serial number. ([RFC 9773§4.1](https://www.rfc-editor.org/rfc/rfc9773.html#section-4.1))
+fn certificate_id(cert: &Certificate) -> Result<String, Error> {
+    let key_identifier = cert.tbs_certificate.get::<AuthorityKeyIdentifier>()
+        .map_err(|e| Error::CertificateParse(e.to_string()))?
+        .and_then(|(_, aki)| aki.key_identifier)
+        .ok_or(Error::CertificateParse("missing authority key identifier".to_string()))?;
+
+    // The serial number's DER encoded value, without tag and length, but including any leading zero.
+    let serial = cert.tbs_certificate.serial_number.to_der()
+        .map_err(|e| Error::CertificateParse(e.to_string()))?;
+    let serial = AnyRef::from_der(&serial)
+        .map_err(|e| Error::CertificateParse(e.to_string()))?;
+
+    Ok(format!("{}.{}",
+        Base64UrlUnpadded::encode_string(key_identifier.as_bytes()),
+        Base64UrlUnpadded::encode_string(serial.value())))
+}
+
+/// How long to wait before asking for renewal information again, if the server doesn't say.
+/// ([RFC 9773§4.3](https://www.rfc-editor.org/rfc/rfc9773.html#section-4.3))
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(6 * 60 * 60);
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Window {
+    start: String,
+    end: String,
+}
+
+/// The Certificate Authority's suggestion on when to renew a certificate.
+/// ([RFC 9773§4.2](https://www.rfc-editor.org/rfc/rfc9773.html#section-4.2))
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RenewalInfo {
+    suggested_window: Window,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub explanation_url: Option<String>,
+
+    /// The ID of the certificate the information is about, the Unix time until which it can be
+    /// reused (following the server's `Retry-After`), and the Unix time chosen within the window to
+    /// renew at. Only kept in stored copies.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cert_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    retry_at: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    renewal_at: Option<i64>,
+}
+
+impl TryFrom<&[u8]> for RenewalInfo {
+    type Error = Error;
+
+    /// Parse a stored copy, see [RenewalInfo::as_bytes].
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        from_json_bytes(bytes)
+    }
+}
+
+impl RenewalInfo {
+    /// Parse the server's response about the certificate with the given ID, noting when to ask again.
+    pub(crate) fn from_response(response: Response, cert_id: &str) -> Result<Self, Error> {
+        let retry_after = retry_after(&response)
+            .unwrap_or(DEFAULT_RETRY_AFTER);
+        let mut renewal_info: Self = response.into_json()
+            .map_err(|e| Error::ResponseIntoJson(e.to_string()))?;
+
+        renewal_info.cert_id = Some(cert_id.to_string());
+        renewal_info.retry_at = Some((OffsetDateTime::now_utc() + retry_after).unix_timestamp());
+        // An invalid window is reported by [RenewalInfo::renewal_time] instead.
+        renewal_info.renewal_at = renewal_info.random_time().ok()
+            .map(OffsetDateTime::unix_timestamp);
+
+        Ok(renewal_info)
+    }
+
+    /// Serialize the information to be stored, and reused while [RenewalInfo::is_current].
+    pub fn as_bytes(&self) -> Result<Vec<u8>, Error> {
+        to_json_vec(self)
+    }
+
+    /// Whether the information is about the given certificate, and the server's `Retry-After` didn't
+    /// pass yet, so there's no need to ask again.
+    pub fn is_current(&self, cert_id: &str) -> bool {
+        self.cert_id.as_deref() == Some(cert_id)
+            && self.retry_at.is_some_and(|retry_at| OffsetDateTime::now_utc().unix_timestamp() < retry_at)
+    }
+
+    /// The start and end of the suggested renewal window.
+    pub fn window(&self) -> Result<(OffsetDateTime, OffsetDateTime), Error> {
+        let parse = |s: &str| OffsetDateTime::parse(s, &Rfc3339)
+            .map_err(|e| Error::RenewalInfo(e.to_string()));
+
+        Ok((parse(&self.suggested_window.start)?, parse(&self.suggested_window.end)?))
+    }
+
+    /// When to renew: the point within the window chosen when the information was fetched. It's
+    /// kept along with the stored copy, as picking a new one on every run would pull renewals
+    /// towards the start of the window.
+    pub fn renewal_time(&self) -> Result<OffsetDateTime, Error> {
+        match self.renewal_at {
+            Some(renewal_at) => OffsetDateTime::from_unix_timestamp(renewal_at)
+                .map_err(|e| Error::RenewalInfo(e.to_string())),
+            None => self.random_time(),
+        }
+    }
+
+    /// A uniformly random point within the suggested window to renew at, spreading renewals across
+    /// the window.
+    fn random_time(&self) -> Result<OffsetDateTime, Error> {
+        let (start, end) = self.window()?;
+        if end <= start {
+            return Ok(start);
+        }
+
+        Ok(start + (end - start) * OsRng.gen_range(0.0..=1.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn renewal_info(start: &str, end: &str) -> RenewalInfo {
+        serde_json::from_str(&format!(r#"{{"suggestedWindow": {{"start": "{start}", "end": "{end}"}}}}"#)).unwrap()
+    }
+
+    fn issued(not_before: &str, not_after: &str) -> IssuedCertificate {
+        IssuedCertificate {
+            id: None,
+            not_before: OffsetDateTime::parse(not_before, &Rfc3339).unwrap(),
+            not_after: OffsetDateTime::parse(not_after, &Rfc3339).unwrap(),
+            names: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn random_time_stays_within_window() {
+        let renewal_info = renewal_info("2025-01-02T00:00:00Z", "2025-01-03T00:00:00Z");
+        let (start, end) = renewal_info.window().unwrap();
+
+        let times: Vec<OffsetDateTime> = (0..1000)
+            .map(|_| renewal_info.random_time().unwrap())
+            .collect();
+        assert!(times.iter().all(|time| (start..=end).contains(time)));
+        // Spread across the window, not fixed to a single point.
+        assert!(times.iter().any(|time| *time < start + time::Duration::hours(12)));
+        assert!(times.iter().any(|time| *time > start + time::Duration::hours(12)));
+    }
+
+    #[test]
+    fn stored_renewal_time_is_kept() {
+        let mut renewal_info = renewal_info("2025-01-02T00:00:00Z", "2025-01-03T00:00:00Z");
+        let chosen = OffsetDateTime::parse("2025-01-02T17:00:00Z", &Rfc3339).unwrap();
+        renewal_info.renewal_at = Some(chosen.unix_timestamp());
+
+        let stored = RenewalInfo::try_from(renewal_info.as_bytes().unwrap().as_slice()).unwrap();
+        assert!((0..100).all(|_| stored.renewal_time().unwrap() == chosen));
+    }
+
+    #[test]
+    fn renewal_time_of_empty_or_inverted_window_is_its_start() {
+        let start = OffsetDateTime::parse("2025-01-02T00:00:00Z", &Rfc3339).unwrap();
+
+        let empty = renewal_info("2025-01-02T00:00:00Z", "2025-01-02T00:00:00Z");
+        assert_eq!(empty.renewal_time().unwrap(), start);
+        let inverted = renewal_info("2025-01-02T00:00:00Z", "2025-01-01T00:00:00Z");
+        assert_eq!(inverted.renewal_time().unwrap(), start);
+    }
+
+    #[test]
+    fn renewal_time_rejects_invalid_timestamps() {
+        let renewal_info = renewal_info("tomorrow", "2025-01-03T00:00:00Z");
+        assert!(matches!(renewal_info.renewal_time(), Err(Error::RenewalInfo(_))));
+    }
+
+    #[test]
+    fn stored_renewal_info_is_current_until_retry_after() {
+        let mut renewal_info = renewal_info("2025-01-02T00:00:00Z", "2025-01-03T00:00:00Z");
+        // Fresh from the server without being stored, it's never reused.
+        assert!(!renewal_info.is_current("aYhba4dGQEHhs3uEe6CuLN4ByNQ.AIdlQyE"));
+
+        renewal_info.cert_id = Some("aYhba4dGQEHhs3uEe6CuLN4ByNQ.AIdlQyE".to_string());
+        renewal_info.retry_at = Some((OffsetDateTime::now_utc() + time::Duration::hours(1)).unix_timestamp());
+        let stored = RenewalInfo::try_from(renewal_info.as_bytes().unwrap().as_slice()).unwrap();
+        assert!(stored.is_current("aYhba4dGQEHhs3uEe6CuLN4ByNQ.AIdlQyE"));
+        assert!(!stored.is_current("aYhba4dGQEHhs3uEe6CuLN4ByNQ.AIdlQyF"));
+
+        renewal_info.retry_at = Some((OffsetDateTime::now_utc() - time::Duration::seconds(1)).unix_timestamp());
+        assert!(!renewal_info.is_current("aYhba4dGQEHhs3uEe6CuLN4ByNQ.AIdlQyE"));
+    }
+
+    #[test]
+    fn expiry_renewal_time_defaults_to_30_days_or_a_third_of_the_lifetime() {
+        let long_lived = issued("2025-01-01T00:00:00Z", "2025-04-01T00:00:00Z");
+        assert_eq!(long_lived.expiry_renewal_time(None), long_lived.not_after - time::Duration::days(30));
+
+        let short_lived = issued("2025-01-01T00:00:00Z", "2025-01-07T00:00:00Z");
+        assert_eq!(short_lived.expiry_renewal_time(None), short_lived.not_after - time::Duration::days(2));
+
+        assert_eq!(long_lived.expiry_renewal_time(Some(time::Duration::days(10))),
+            long_lived.not_after - time::Duration::days(10));
+    }
+}
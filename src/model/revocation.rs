@@ -0,0 +1,56 @@
+use super::*;
+use super::account::*;
+
+/// Certificate revocation reason codes.
+/// ([RFC 5280§5.3.1](https://www.rfc-editor.org/rfc/rfc5280.html#section-5.3.1))
+#[derive(Debug, Clone, Copy)]
+pub enum RevocationReason {
+    Unspecified = 0,
+    KeyCompromise = 1,
+    CaCompromise = 2,
+    AffiliationChanged = 3,
+    Superseded = 4,
+    CessationOfOperation = 5,
+    CertificateHold = 6,
+    RemoveFromCrl = 8,
+    PrivilegeWithdrawn = 9,
+    AaCompromise = 10,
+}
+
+impl TryFrom<&str> for RevocationReason {
+    type Error = Error;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s {
+            "unspecified" => Ok(Self::Unspecified),
+            "keyCompromise" => Ok(Self::KeyCompromise),
+            "cACompromise" => Ok(Self::CaCompromise),
+            "affiliationChanged" => Ok(Self::AffiliationChanged),
+            "superseded" => Ok(Self::Superseded),
+            "cessationOfOperation" => Ok(Self::CessationOfOperation),
+            "certificateHold" => Ok(Self::CertificateHold),
+            "removeFromCRL" => Ok(Self::RemoveFromCrl),
+            "privilegeWithdrawn" => Ok(Self::PrivilegeWithdrawn),
+            "aACompromise" => Ok(Self::AaCompromise),
+            _ => Err(Error::UnknownRevocationReason(s.to_string())),
+        }
+    }
+}
+
+/// Revoke a certificate with a request signed by the certificate's own (PKCS#8 DER encoded) private key,
+/// for when the account that requested it is unavailable.
+/// ([RFC 8555§7.6](https://www.rfc-editor.org/rfc/rfc8555.html#section-7.6))
+pub fn revoke_with_certificate_key(ca: &CertificateAuthority, cert_der: &[u8], key_der: &[u8], reason: Option<RevocationReason>) -> Result<(), Error> {
+    let directory: Directory = get_as_json(ca.url())?;
+    let crypto = Crypto::from_pkcs8_der(key_der)?;
+    let nonce = new_nonce(&directory.new_nonce)?;
+
+    let payload = Payload::RevokeCert {
+        certificate: Base64UrlUnpadded::encode_string(cert_der),
+        reason: reason.map(|r| r as u8),
+    };
+
+    crypto.post(&directory.revoke_cert, nonce, &directory.new_nonce, payload)?;
+
+    Ok(())
+}
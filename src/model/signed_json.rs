@@ -1,9 +1,9 @@
 use super::Serialize;
 
-#[derive(Debug, Serialize)]
-#[serde(rename_all = "lowercase")]
-pub(crate) enum SignedJsonHeaderKey {
-    Jwk {
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub(crate) enum Jwk {
+    Ec {
         alg: &'static str,
         crv: &'static str,
         kty: &'static str,
@@ -13,6 +13,42 @@ pub(crate) enum SignedJsonHeaderKey {
         y: String,
     },
 
+    Rsa {
+        alg: &'static str,
+        e: String,
+        kty: &'static str,
+        n: String,
+        #[serde(rename = "use")]
+        usage: &'static str,
+    },
+
+    Okp {
+        alg: &'static str,
+        crv: &'static str,
+        kty: &'static str,
+        #[serde(rename = "use")]
+        usage: &'static str,
+        x: String,
+    },
+}
+
+impl Jwk {
+    /// Only the required members, in lexicographic order.
+    /// ([RFC 7638§3.2](https://www.rfc-editor.org/rfc/rfc7638.html#section-3.2))
+    pub fn thumbprint_data(&self) -> SignedJsonThumbprint<'_> {
+        match self {
+            Self::Ec { crv, kty, x, y, .. } => SignedJsonThumbprint::Ec { crv, kty, x, y },
+            Self::Rsa { e, kty, n, .. } => SignedJsonThumbprint::Rsa { e, kty, n },
+            Self::Okp { crv, kty, x, .. } => SignedJsonThumbprint::Okp { crv, kty, x },
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum SignedJsonHeaderKey {
+    Jwk(Jwk),
+
     Kid(String),
 }
 
@@ -21,11 +57,12 @@ pub(crate) struct SignedJsonHeader<'a> {
     pub alg: &'static str,
     #[serde(flatten)]
     pub key: &'a SignedJsonHeaderKey,
-    pub nonce: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
     pub url: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub(crate) struct SignedJson {
     pub protected: String,
     pub payload: String,
@@ -33,9 +70,24 @@ pub(crate) struct SignedJson {
 }
 
 #[derive(Serialize)]
-pub(crate) struct SignedJsonThumbprint {
-    pub crv: &'static str,
-    pub kty: &'static str,
-    pub x: String,
-    pub y: String,
+#[serde(untagged)]
+pub(crate) enum SignedJsonThumbprint<'a> {
+    Ec {
+        crv: &'a str,
+        kty: &'a str,
+        x: &'a str,
+        y: &'a str,
+    },
+
+    Rsa {
+        e: &'a str,
+        kty: &'a str,
+        n: &'a str,
+    },
+
+    Okp {
+        crv: &'a str,
+        kty: &'a str,
+        x: &'a str,
+    },
 }
\ No newline at end of file
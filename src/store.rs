@@ -0,0 +1,574 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use p12_keystore::{KeyStore, KeyStoreEntry, PrivateKeyChain};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::model::account::Account;
+use crate::model::renewal::RenewalInfo;
+use crate::model::{decode_pem, encode_pem, CertificateAuthority, Error, ExternalAccountBinding};
+
+/// Keeps ACME accounts on disk, one file per Certificate Authority directory URL.
+pub struct AccountStore {
+    directory: PathBuf,
+}
+
+impl AccountStore {
+    /// Use the given directory to store account files.
+    pub fn new(directory: PathBuf) -> Self {
+        Self { directory }
+    }
+
+    /// Build the account file path for the given [CertificateAuthority].
+    ///   e.g. "https://acme-v02.api.letsencrypt.org/directory" -> "acme-v02.api.letsencrypt.org_directory.account"
+    fn path(&self, ca: &CertificateAuthority) -> PathBuf {
+        let url = ca.url();
+        let stem: String = url.split_once("://")
+            .map_or(url, |(_, rest)| rest)
+            .trim_end_matches('/')
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '.' { c } else { '_' })
+            .collect();
+
+        self.directory.join(format!("{stem}.account"))
+    }
+
+    /// Load the stored account for the given [CertificateAuthority], if there is one.
+    pub fn load(&self, ca: &CertificateAuthority) -> Result<Option<Account>, String> {
+        let path = self.path(ca);
+        if !path.is_file() {
+            return Ok(None);
+        }
+
+        let account_bytes = std::fs::read(&path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        let mut account = Account::try_from(account_bytes.as_slice())
+            .map_err(|e| match e {
+                Error::AccountStatus(status) => format!("The account stored in {} is {status} and can no longer \
+                    be used. Remove the file to generate a new account", path.display()),
+                e => format!("{}: {}", path.display(), e),
+            })?;
+        account.refresh_directory(ca)
+            .map_err(|e| e.to_string())?;
+
+        Ok(Some(account))
+    }
+
+    /// Write the given account to the store, replacing any previously stored account for the
+    /// same [CertificateAuthority]. The file holds the account's private key, so it is only
+    /// made readable by its owner.
+    pub fn save(&self, ca: &CertificateAuthority, account: &Account) -> Result<(), String> {
+        let path = self.path(ca);
+        let account_bytes = account.as_bytes()
+            .map_err(|e| e.to_string())?;
+
+        std::fs::create_dir_all(&self.directory)
+            .map_err(|e| format!("{}: {}", self.directory.display(), e))?;
+
+        write_atomically(&path, &account_bytes, PRIVATE_MODE, None, None)
+    }
+
+    /// Load the stored account for the given [CertificateAuthority], or generate and store a new one.
+    pub fn load_or_generate(&self, ca: &CertificateAuthority, contact: &[String], terms_of_service: Option<&str>,
+        eab: Option<&ExternalAccountBinding>) -> Result<Account, String> {
+        if let Some(account) = self.load(ca)? {
+            return Ok(account);
+        }
+
+        let account = Account::generate(ca, contact, terms_of_service, eab)
+            .map_err(|e| match e {
+                Error::TermsOfServiceNotAgreed(url) => format!("The Certificate Authority's terms of service must be \
+                    agreed to by setting 'terms_of_service = \"{url}\"' in the configuration file"),
+                Error::ExternalAccountRequired => "The Certificate Authority requires an external account \
+                    binding, set 'eab.kid' and 'eab.hmac_key' in the configuration file".to_string(),
+                e => e.to_string(),
+            })?;
+        self.save(ca, &account)?;
+
+        Ok(account)
+    }
+}
+
+/// The files a certificate can be written as, named after the certificate.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Output {
+    /// `<name>.pem`: the certificate chain as downloaded.
+    Pem,
+    /// `<name>.der`: the DER encoded PKCS#8 private key.
+    Der,
+    /// `<name>.key.pem`: the PEM encoded PKCS#8 private key.
+    Key,
+    /// `<name>.cert.pem`: only the certificate itself.
+    Cert,
+    /// `<name>.chain.pem`: only the issuer certificates.
+    Chain,
+    /// `<name>.fullchain.pem`: the certificate followed by its issuers.
+    Fullchain,
+    /// `<name>.combined.pem`: the private key followed by the full chain, e.g. for HAProxy.
+    Combined,
+    /// `<name>.p12`: a PKCS#12 bundle of the private key and full chain, e.g. for Java and Windows.
+    #[serde(alias = "p12", alias = "pfx")]
+    Pkcs12,
+}
+
+/// The files written when none are configured.
+pub const DEFAULT_OUTPUTS: &[Output] = &[Output::Pem, Output::Der];
+
+impl Output {
+    /// Whether the output holds the private key.
+    fn has_key(self) -> bool {
+        matches!(self, Self::Der | Self::Key | Self::Combined | Self::Pkcs12)
+    }
+
+    fn file_name(self, name: &str) -> String {
+        match self {
+            Self::Pem => format!("{name}.pem"),
+            Self::Der => format!("{name}.der"),
+            Self::Key => format!("{name}.key.pem"),
+            Self::Cert => format!("{name}.cert.pem"),
+            Self::Chain => format!("{name}.chain.pem"),
+            Self::Fullchain => format!("{name}.fullchain.pem"),
+            Self::Combined => format!("{name}.combined.pem"),
+            Self::Pkcs12 => format!("{name}.p12"),
+        }
+    }
+}
+
+/// The permissions of files holding a private key, only readable by their owner.
+const PRIVATE_MODE: u32 = 0o600;
+
+/// The permissions of certificate files.
+const PUBLIC_MODE: u32 = 0o644;
+
+/// How the files of a certificate are written.
+#[derive(Debug)]
+pub struct OutputOptions<'a> {
+    pub outputs: &'a [Output],
+    pub pkcs12_password: Option<&'a str>,
+    /// The user ID to give the files to, instead of the current user.
+    pub owner: Option<u32>,
+    /// The group ID to give the files to, instead of the current user's group.
+    pub group: Option<u32>,
+}
+
+/// Keeps issued certificates and their private keys on disk, in each of the configured [Output]s.
+///
+/// When archiving, each issued version is kept in its own `archive/<name>/<version>` directory, and
+/// `live/<name>` links to the one in use.
+pub struct CertificateStore {
+    directory: PathBuf,
+    archive: bool,
+}
+
+impl CertificateStore {
+    /// Use the given directory to store certificate files, optionally keeping an archive of every version.
+    pub fn new(directory: PathBuf, archive: bool) -> Self {
+        Self { directory, archive }
+    }
+
+    /// The directory holding the files of the certificate currently in use.
+    fn live_directory(&self, name: &str) -> PathBuf {
+        match self.archive {
+            true => self.directory.join("live").join(name),
+            false => self.directory.clone(),
+        }
+    }
+
+    fn archive_directory(&self, name: &str) -> PathBuf {
+        self.directory.join("archive").join(name)
+    }
+
+    /// The archived versions of the certificate, in ascending order.
+    fn versions(&self, name: &str) -> Result<Vec<u32>, String> {
+        let archive_dir = self.archive_directory(name);
+        if !archive_dir.is_dir() {
+            return Ok(Vec::new());
+        }
+
+        let mut versions: Vec<u32> = std::fs::read_dir(&archive_dir)
+            .map_err(|e| format!("{}: {}", archive_dir.display(), e))?
+            .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+            .collect();
+        versions.sort_unstable();
+
+        Ok(versions)
+    }
+
+    /// The archived version `live/<name>` currently links to, if any.
+    fn live_version(&self, name: &str) -> Option<u32> {
+        std::fs::read_link(self.live_directory(name)).ok()?
+            .file_name()?
+            .to_str()?
+            .parse()
+            .ok()
+    }
+
+    /// Point `live/<name>` to the archived version, replacing the previous link in a single step.
+    fn link_live(&self, name: &str, version: u32) -> Result<(), String> {
+        let live_dir = self.directory.join("live");
+        std::fs::create_dir_all(&live_dir)
+            .map_err(|e| format!("{}: {}", live_dir.display(), e))?;
+
+        let target = Path::new("..").join("archive").join(name).join(version.to_string());
+        let link_path = live_dir.join(name);
+        let temp_path = live_dir.join(format!(".{name}.tmp"));
+        let _ = std::fs::remove_file(&temp_path);
+
+        #[cfg(unix)]
+        let linked = std::os::unix::fs::symlink(&target, &temp_path);
+        #[cfg(windows)]
+        let linked = std::os::windows::fs::symlink_dir(&target, &temp_path);
+
+        linked.and_then(|_| std::fs::rename(&temp_path, &link_path))
+            .map_err(|e| format!("{}: {}", link_path.display(), e))
+    }
+
+    /// Write the PEM encoded certificate chain and its PKCS#8 DER encoded private key as each of the
+    /// outputs. Outputs holding the private key are skipped if there is none, e.g. for an external CSR.
+    pub fn save(&self, name: &str, options: &OutputOptions, chain_pem: &str, key_der: Option<&[u8]>)
+        -> Result<(), String> {
+        let chain = decode_pem(chain_pem, "CERTIFICATE")
+            .map_err(|e| e.to_string())?;
+        let Some((cert, issuers)) = chain.split_first() else {
+            return Err("The downloaded certificate chain is empty".to_string());
+        };
+        let issuers_pem: String = issuers.iter()
+            .map(|issuer| encode_pem("CERTIFICATE", issuer))
+            .collect();
+        let full_chain_pem = format!("{}{issuers_pem}", encode_pem("CERTIFICATE", cert));
+
+        // A new version is written into a temporary directory, which only becomes the version (and
+        // goes live) once all of its files are written.
+        let version = match self.archive {
+            true => Some(self.versions(name)?.last().map_or(1, |latest| latest + 1)),
+            false => None,
+        };
+        let directory = match version {
+            Some(version) => {
+                let directory = self.archive_directory(name).join(format!(".{version}.tmp"));
+                let _ = std::fs::remove_dir_all(&directory);
+                directory
+            },
+            None => self.directory.clone(),
+        };
+        std::fs::create_dir_all(&directory)
+            .map_err(|e| format!("{}: {}", directory.display(), e))?;
+
+        // Write every file before replacing any, so failing halfway (e.g. on a full disk) never
+        // leaves a new certificate next to an old key. Only with `archive` are they swapped in a
+        // single step though, otherwise a crash between the renames can still mix versions.
+        let mut written: Vec<(PathBuf, PathBuf)> = Vec::new();
+        let all_written = options.outputs.iter().try_for_each(|&output| {
+            let contents = match (output, key_der) {
+                (Output::Pem, _) => chain_pem.as_bytes().to_vec(),
+                (Output::Cert, _) => encode_pem("CERTIFICATE", cert).into_bytes(),
+                (Output::Chain, _) => issuers_pem.clone().into_bytes(),
+                (Output::Fullchain, _) => full_chain_pem.clone().into_bytes(),
+                (Output::Der, Some(key_der)) => key_der.to_vec(),
+                (Output::Key, Some(key_der)) => encode_pem("PRIVATE KEY", key_der).into_bytes(),
+                (Output::Combined, Some(key_der)) => format!("{}{full_chain_pem}", encode_pem("PRIVATE KEY", key_der))
+                    .into_bytes(),
+                (Output::Pkcs12, Some(key_der)) => pkcs12(name, key_der, &chain, options.pkcs12_password.unwrap_or(""))?,
+                (_, None) => return Ok(()),
+            };
+
+            let path = directory.join(output.file_name(name));
+            let mode = if output.has_key() { PRIVATE_MODE } else { PUBLIC_MODE };
+            written.push((write_temporary(&path, &contents, mode, options.owner, options.group)?, path));
+            Ok(())
+        });
+
+        let all_renamed = all_written.and_then(|_| written.iter()
+            .try_for_each(|(temp_path, path)| std::fs::rename(temp_path, path)
+                .map_err(|e| format!("{}: {}", path.display(), e))))
+            .and_then(|_| sync_directory(&directory));
+
+        let Some(version) = version else {
+            if all_renamed.is_err() {
+                for (temp_path, _) in &written {
+                    let _ = std::fs::remove_file(temp_path);
+                }
+            }
+            return all_renamed;
+        };
+
+        let version_directory = self.archive_directory(name).join(version.to_string());
+        let archived = all_renamed
+            .and_then(|_| std::fs::rename(&directory, &version_directory)
+                .map_err(|e| format!("{}: {}", version_directory.display(), e)))
+            .and_then(|_| sync_directory(&self.archive_directory(name)));
+        if let Err(e) = archived {
+            let _ = std::fs::remove_dir_all(&directory);
+            return Err(e);
+        }
+
+        self.link_live(name, version)
+    }
+
+    /// Point `live/<name>` back to the archived version before the one in use, returning that version.
+    pub fn rollback(&self, name: &str) -> Result<u32, String> {
+        if !self.archive {
+            return Err("Rolling back needs 'archive' to be enabled".to_string());
+        }
+
+        let live_version = self.live_version(name)
+            .ok_or(format!("{} is not a link to an archived version", self.live_directory(name).display()))?;
+        let previous = self.versions(name)?.into_iter()
+            .rev()
+            .find(|&version| version < live_version)
+            .ok_or(format!("There is no version of '{name}' before version {live_version}"))?;
+
+        self.link_live(name, previous)?;
+
+        Ok(previous)
+    }
+
+    /// Load the renewal information stored for the certificate currently in use, if any.
+    pub fn load_renewal_info(&self, name: &str) -> Option<RenewalInfo> {
+        let path = self.live_directory(name).join(format!("{name}.ari.json"));
+        let bytes = std::fs::read(path).ok()?;
+
+        RenewalInfo::try_from(bytes.as_slice()).ok()
+    }
+
+    /// Store the renewal information for the certificate currently in use, replacing any stored before.
+    pub fn save_renewal_info(&self, name: &str, renewal_info: &RenewalInfo) -> Result<(), String> {
+        let path = self.live_directory(name).join(format!("{name}.ari.json"));
+        let bytes = renewal_info.as_bytes()
+            .map_err(|e| e.to_string())?;
+
+        write_atomically(&path, &bytes, PUBLIC_MODE, None, None)
+    }
+
+    /// Load the DER encoded certificate from the first of the outputs that holds it, if any was written.
+    pub fn load_certificate(&self, name: &str, options: &OutputOptions) -> Result<Option<Vec<u8>>, String> {
+        for &output in options.outputs {
+            let path = self.live_directory(name).join(output.file_name(name));
+            if !path.is_file() {
+                continue;
+            }
+
+            let cert_der = match output {
+                Output::Pem | Output::Cert | Output::Fullchain | Output::Combined => read_pem(&path, "CERTIFICATE")?,
+                Output::Pkcs12 => read_pkcs12(&path, options.pkcs12_password.unwrap_or(""))?
+                    .chain().first()
+                    .map(|cert| cert.as_der().to_vec()),
+                Output::Der | Output::Key | Output::Chain => continue,
+            };
+
+            return cert_der.map(Some)
+                .ok_or(format!("{} contains no certificate", path.display()));
+        }
+
+        Ok(None)
+    }
+
+    /// Load the PKCS#8 DER encoded private key from the first of the outputs that holds it, if any was written.
+    pub fn load_key(&self, name: &str, options: &OutputOptions) -> Result<Option<Vec<u8>>, String> {
+        for &output in options.outputs {
+            let path = self.live_directory(name).join(output.file_name(name));
+            if !path.is_file() {
+                continue;
+            }
+
+            let key_der = match output {
+                Output::Der => Some(std::fs::read(&path)
+                    .map_err(|e| format!("{}: {}", path.display(), e))?),
+                Output::Key | Output::Combined => read_pem(&path, "PRIVATE KEY")?,
+                Output::Pkcs12 => Some(read_pkcs12(&path, options.pkcs12_password.unwrap_or(""))?
+                    .key().to_vec()),
+                Output::Pem | Output::Cert | Output::Chain | Output::Fullchain => continue,
+            };
+
+            return key_der.map(Some)
+                .ok_or(format!("{} contains no private key", path.display()));
+        }
+
+        Ok(None)
+    }
+}
+
+/// Write the file by way of a temporary file that's renamed over it, so it's never left half-written.
+fn write_atomically(path: &Path, contents: &[u8], mode: u32, owner: Option<u32>, group: Option<u32>)
+    -> Result<(), String> {
+    let temp_path = write_temporary(path, contents, mode, owner, group)?;
+    if let Err(e) = std::fs::rename(&temp_path, path) {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(format!("{}: {}", path.display(), e));
+    }
+
+    match path.parent() {
+        Some(directory) => sync_directory(directory),
+        None => Ok(()),
+    }
+}
+
+/// Write the contents to a temporary file next to the path, returning the temporary file's path
+/// for it to be renamed over the path. The temporary file is created with the given permissions and
+/// owner, so the contents are never readable by anyone else.
+fn write_temporary(path: &Path, contents: &[u8], mode: u32, owner: Option<u32>, group: Option<u32>)
+    -> Result<PathBuf, String> {
+    let file_name = path.file_name()
+        .and_then(|file_name| file_name.to_str())
+        .ok_or(format!("{}: invalid file name", path.display()))?;
+    let temp_path = path.with_file_name(format!(".{file_name}.tmp"));
+    let _ = std::fs::remove_file(&temp_path);
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, mode);
+    #[cfg(not(unix))]
+    let _ = mode;
+
+    let written = options.open(&temp_path)
+        .and_then(|mut file| {
+            file.write_all(contents)?;
+            file.sync_all()
+        })
+        .and_then(|_| change_owner(&temp_path, owner, group));
+    if let Err(e) = written {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(format!("{}: {}", path.display(), e));
+    }
+
+    Ok(temp_path)
+}
+
+/// Make sure renames within the directory survive a crash.
+#[cfg(unix)]
+fn sync_directory(directory: &Path) -> Result<(), String> {
+    std::fs::File::open(directory)
+        .and_then(|directory| directory.sync_all())
+        .map_err(|e| format!("{}: {}", directory.display(), e))
+}
+
+#[cfg(not(unix))]
+fn sync_directory(_directory: &Path) -> Result<(), String> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn change_owner(path: &Path, owner: Option<u32>, group: Option<u32>) -> std::io::Result<()> {
+    if owner.is_none() && group.is_none() {
+        return Ok(());
+    }
+
+    std::os::unix::fs::chown(path, owner, group)
+}
+
+#[cfg(not(unix))]
+fn change_owner(_path: &Path, owner: Option<u32>, group: Option<u32>) -> std::io::Result<()> {
+    match (owner, group) {
+        (None, None) => Ok(()),
+        _ => Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "file owners can only be set on Unix")),
+    }
+}
+
+/// Read the first PEM block with the given label from the file.
+fn read_pem(path: &Path, label: &str) -> Result<Option<Vec<u8>>, String> {
+    let pem = std::fs::read_to_string(path)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    decode_pem(&pem, label)
+        .map(|blocks| blocks.into_iter().next())
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Read the private key and certificate chain from the PKCS#12 file.
+fn read_pkcs12(path: &Path, password: &str) -> Result<PrivateKeyChain, String> {
+    let data = std::fs::read(path)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    let key_store = KeyStore::from_pkcs12(&data, password)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    key_store.private_key_chain()
+        .map(|(_, key_chain)| key_chain.clone())
+        .ok_or(format!("{} contains no private key", path.display()))
+}
+
+/// Bundle the private key and certificate chain into a PKCS#12 file, encrypted with the password.
+fn pkcs12(name: &str, key_der: &[u8], chain: &[Vec<u8>], password: &str) -> Result<Vec<u8>, String> {
+    let certs = chain.iter()
+        .map(|cert_der| p12_keystore::Certificate::from_der(cert_der))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    // Ties the key to its certificate, for which a digest of the certificate is conventionally used.
+    let local_key_id = Sha256::digest(&chain[0]);
+
+    let mut key_store = KeyStore::new();
+    key_store.add_entry(name, KeyStoreEntry::PrivateKeyChain(PrivateKeyChain::new(key_der, local_key_id, certs)));
+    key_store.writer(password)
+        .write()
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failed_save_replaces_no_files() {
+        let directory = std::env::temp_dir().join(format!("snacme-store-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        let store = CertificateStore::new(directory.clone(), false);
+        let options = OutputOptions {
+            outputs: &[Output::Fullchain, Output::Key],
+            pkcs12_password: None,
+            owner: None,
+            group: None,
+        };
+
+        let old_chain = encode_pem("CERTIFICATE", b"old");
+        store.save("a", &options, &old_chain, Some(b"old key")).unwrap();
+
+        // Writing the key's temporary file fails, after the certificate's was written.
+        std::fs::create_dir_all(directory.join(".a.key.pem.tmp").join("in the way")).unwrap();
+        let new_chain = encode_pem("CERTIFICATE", b"new");
+        assert!(store.save("a", &options, &new_chain, Some(b"new key")).is_err());
+
+        assert_eq!(store.load_certificate("a", &options).unwrap(), Some(b"old".to_vec()));
+        assert_eq!(store.load_key("a", &options).unwrap(), Some(b"old key".to_vec()));
+        assert!(!directory.join(".a.fullchain.pem.tmp").exists());
+
+        let _ = std::fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn rollback_after_failed_save_skips_the_failed_version() {
+        let directory = std::env::temp_dir().join(format!("snacme-archive-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        let store = CertificateStore::new(directory.clone(), true);
+        let options = OutputOptions {
+            outputs: &[Output::Fullchain, Output::Key],
+            pkcs12_password: None,
+            owner: None,
+            group: None,
+        };
+
+        for version in ["1", "2"] {
+            let chain = encode_pem("CERTIFICATE", version.as_bytes());
+            store.save("a", &options, &chain, Some(version.as_bytes())).unwrap();
+        }
+
+        // The chain isn't a certificate, so the PKCS#12 bundle can't be made after the other files
+        // of version 3 were written.
+        let failing_options = OutputOptions { outputs: &[Output::Fullchain, Output::Key, Output::Pkcs12], ..options };
+        let chain = encode_pem("CERTIFICATE", b"3");
+        assert!(store.save("a", &failing_options, &chain, Some(b"3")).is_err());
+
+        assert_eq!(store.versions("a").unwrap(), [1, 2]);
+        assert_eq!(std::fs::read_dir(store.archive_directory("a")).unwrap().count(), 2);
+        assert_eq!(store.live_version("a"), Some(2));
+
+        assert_eq!(store.rollback("a").unwrap(), 1);
+        assert_eq!(store.load_certificate("a", &options).unwrap(), Some(b"1".to_vec()));
+        assert_eq!(store.load_key("a", &options).unwrap(), Some(b"1".to_vec()));
+
+        let _ = std::fs::remove_dir_all(&directory);
+    }
+}